@@ -0,0 +1,35 @@
+use anyhow::Result;
+use candid::Nat;
+
+use crate::{
+    log,
+    types::{chains::Chains, logger::CHAINS},
+    utils::web3,
+};
+
+/// Resume the chains paused due to errors if their RPC responds again
+pub async fn probe() {
+    for chain_id in Chains::get_due_for_probe() {
+        if let Err(err) = probe_chain(&chain_id).await {
+            log!("[{CHAINS}] failed to probe the chain {}: {err:?}", chain_id);
+        }
+    }
+}
+
+async fn probe_chain(chain_id: &Nat) -> Result<()> {
+    match web3::gas_price(chain_id).await {
+        Ok(_) => {
+            Chains::resume(chain_id)?;
+            log!("[{CHAINS}] chain {} is resumed after the probe", chain_id);
+        }
+        Err(err) => {
+            Chains::postpone_probe(chain_id)?;
+            log!(
+                "[{CHAINS}] chain {} is still unavailable: {err:?}",
+                chain_id
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use candid::Nat;
+
+use crate::{
+    log,
+    types::{
+        balance::Balances, chains::Chains, errors::PythiaError, logger::CHAINS,
+        subscription::Subscriptions, withdraw::WithdrawRequests, withdraw::WithdrawStatus,
+    },
+    utils::{canister, erc20, web3},
+};
+
+/// Enqueue the withdrawals of the balances left on the decommissioning chain and remove
+/// the chain once every withdrawal request on it is confirmed or abandoned
+pub async fn decommission(chain_id: &Nat) -> Result<()> {
+    let ids = enqueue_withdrawals(chain_id).await?;
+    if !ids.is_empty() {
+        return Ok(());
+    }
+
+    let is_pending = WithdrawRequests::get_by_chain(chain_id)
+        .iter()
+        .any(|req| !req.status.is_final());
+    if is_pending {
+        return Ok(());
+    }
+
+    remove(chain_id)
+}
+
+/// Withdraw every user's balance back to the user's address, the PMA's own balance is kept.
+/// Balances whose withdrawal was abandoned are not withdrawn again and are left as dust
+async fn enqueue_withdrawals(chain_id: &Nat) -> Result<Vec<Nat>> {
+    let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
+    let abandoned = WithdrawRequests::get_by_chain(chain_id)
+        .into_iter()
+        .filter(|req| matches!(req.status, WithdrawStatus::Abandoned { .. }))
+        .map(|req| req.owner)
+        .collect::<HashSet<_>>();
+    let (balances, abandoned): (Vec<_>, Vec<_>) = Balances::get_chain_balances(chain_id)?
+        .into_iter()
+        .filter(|(address, _)| *address != pma)
+        .partition(|(address, _)| !abandoned.contains(address));
+
+    let gas_price = web3::gas_price(chain_id)
+        .await
+        .context(PythiaError::UnableToGetGasPrice)?;
+    let rates = if balances.iter().any(|(_, balance)| balance.has_tokens()) {
+        erc20::rates(chain_id).await?
+    } else {
+        Default::default()
+    };
+
+    let mut ids = vec![];
+    let mut dust_balances = abandoned
+        .into_iter()
+        .filter(|(_, balance)| balance.amount > Nat::from(0) || balance.has_tokens())
+        .map(|(address, _)| address)
+        .collect::<Vec<_>>();
+    for (address, balance) in balances {
+        #[allow(clippy::cmp_owned)]
+        if balance.amount > balance.reserved_total() {
+            match Balances::get_values_for_withdraw(
+                chain_id,
+                &address,
+                &gas_price,
+                &[(address.clone(), None)],
+            ) {
+                Ok(values) => {
+                    for value in values {
+                        ids.push(WithdrawRequests::add(
+                            chain_id, &address, &address, &value, &value, None,
+                        )?);
+                    }
+                }
+                Err(err) => {
+                    log!(
+                        "[{CHAINS}] chain: {}, {address} balance is not withdrawn: {err:?}",
+                        chain_id
+                    );
+                    dust_balances.push(address.clone());
+                }
+            }
+        }
+
+        for (token, amount) in balance.tokens.iter().flatten() {
+            #[allow(clippy::cmp_owned)]
+            if *amount == Nat::from(0) {
+                continue;
+            }
+
+            let rate = rates
+                .get(token)
+                .context(PythiaError::UnableToGetTokenRate)?;
+            let gas =
+                rate.to_token(&(Nat::from(erc20::TOKEN_TRANSFER_GAS_LIMIT) * gas_price.clone()));
+            match Balances::get_value_for_token_withdraw(
+                chain_id, &address, token, &gas, &address, &rates,
+            ) {
+                Ok(value) => ids.push(WithdrawRequests::add(
+                    chain_id,
+                    &address,
+                    &address,
+                    &value,
+                    &(value.clone() + gas),
+                    Some(token.clone()),
+                )?),
+                Err(err) => {
+                    log!(
+                        "[{CHAINS}] chain: {}, {address} {token} balance is not withdrawn: {err:?}",
+                        chain_id
+                    );
+                    if !dust_balances.contains(&address) {
+                        dust_balances.push(address.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    Chains::update_decommission(chain_id, &ids, dust_balances)?;
+    if !ids.is_empty() {
+        log!(
+            "[{CHAINS}] chain: {}, {} decommission withdrawals enqueued",
+            chain_id,
+            ids.len()
+        );
+    }
+
+    Ok(ids)
+}
+
+/// Remove the chain, the PMA's collected fees and the dust balances are recorded in the ledger
+/// and the funds stay on the PMA
+fn remove(chain_id: &Nat) -> Result<()> {
+    Chains::remove(chain_id).context(PythiaError::UnableToRemoveChain)?;
+
+    Balances::remove_chain(chain_id).context(PythiaError::UnableToRemoveChain)?;
+    Subscriptions::deinit_chain(chain_id).context(PythiaError::UnableToRemoveChain)?;
+    WithdrawRequests::deinit_chain(chain_id).context(PythiaError::UnableToRemoveChain)?;
+
+    log!("[{CHAINS}] decommissioned and removed, id: {chain_id}");
+    Ok(())
+}
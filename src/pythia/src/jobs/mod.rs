@@ -1,3 +1,7 @@
+pub mod chains_prober;
+pub mod decommission;
 pub mod publisher;
 pub mod subscriptions_grouper;
+pub mod sweeper;
+pub mod whitelist_expirer;
 pub mod withdraw;
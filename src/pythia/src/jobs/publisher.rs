@@ -1,26 +1,32 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::{Context, Result};
 use candid::Nat;
 use ic_cdk_timers::set_timer;
 
 use futures::future::join_all;
+use ic_web3_rs::{Transport, Web3};
 use thiserror::Error;
 
-use super::{subscriptions_grouper, withdraw};
+use super::{chains_prober, subscriptions_grouper, whitelist_expirer, withdraw};
 use crate::{
-    clone_with_state, log, retry_until_success,
+    clone_with_state, log, metrics, retry_until_success,
     types::{
         balance::Balances,
         chains::Chains,
         errors::PythiaError,
+        ledger::LedgerEntryKind,
         logger::PUBLISHER,
+        operators::Actor,
+        runway::GasPriceHistory,
         subscription::{Subscription, Subscriptions, UpdateSubscriptionRequest},
         timer::Timer,
+        whitelist,
     },
     utils::{
         abi, address, canister,
-        multicall::{multicall, Call},
+        erc20::{self, TokenRate},
+        multicall::{estimate_l1_fees, multicall, Call},
         nat, time, web3,
     },
 };
@@ -53,6 +59,8 @@ async fn _execute() -> Result<()> {
     Timer::update(timer_id).context(PythiaError::UnableToUpdateTimer)?;
 
     subscriptions_grouper::group()?;
+    whitelist_expirer::expire();
+    chains_prober::probe().await;
 
     let (publishable_subs, is_active) = Subscriptions::get_publishable().await;
 
@@ -133,6 +141,17 @@ async fn publish_on_chain(
         .await
         .context(PythiaError::UnableToGetPMA)
         .map_err(PublishOnChainError::ChainError)?;
+    let token_rates = erc20::rates(&chain_id)
+        .await
+        .map_err(PublishOnChainError::ChainError)?;
+    let fee = canister::fee(&chain_id)
+        .await
+        .context("Unable to get fee")
+        .map_err(PublishOnChainError::ChainError)?;
+    let max_gas_price = Chains::get(&chain_id)
+        .map_err(PublishOnChainError::ChainError)?
+        .max_gas_price
+        .map(|max_gas_price| nat::to_u256(&max_gas_price));
 
     while !subscriptions.is_empty() {
         log!(
@@ -141,15 +160,6 @@ async fn publish_on_chain(
             subscriptions.len()
         );
 
-        let calls = get_calls_from_subs(&subscriptions).await?;
-
-        log!("[{PUBLISHER}] Calls inited, chain: {}", chain_id);
-
-        let fee = canister::fee(&chain_id) // TODO: move out of loop
-            .await
-            .context("Unable to get fee")
-            .map_err(PublishOnChainError::ChainError)?;
-
         log!("[{PUBLISHER}] Trying to get gas_price: {}", chain_id);
 
         let mut gas_price =
@@ -169,21 +179,98 @@ async fn publish_on_chain(
             fee
         );
 
+        GasPriceHistory::record(&chain_id, &nat::from_u256(&gas_price), &fee, &token_rates);
+
         // multiply the gas_price to 1.2 to avoid long transaction confirmation
+        let current_gas_price = gas_price;
         gas_price = (gas_price / 10) * 12;
-        let multicall_results = multicall(&w3, &chain_id, calls.clone(), gas_price)
+
+        // the ceilings are compared with the network's gas price, the chain's ceiling
+        // defers the whole batch, otherwise it caps the multiplied price
+        if let Some(max_gas_price) = &max_gas_price {
+            if current_gas_price > *max_gas_price {
+                defer_subs(
+                    &chain_id,
+                    &subscriptions,
+                    format!(
+                        "gas price {current_gas_price} exceeds the chain's max gas price {max_gas_price}"
+                    ),
+                );
+                break;
+            }
+            gas_price = gas_price.min(*max_gas_price);
+        }
+
+        let (affordable, deferred): (Vec<_>, Vec<_>) = subscriptions.into_iter().partition(|sub| {
+            sub.max_gas_price.as_ref().map_or(true, |max_gas_price| {
+                nat::from_u256(&current_gas_price) <= *max_gas_price
+            })
+        });
+        for sub in &deferred {
+            defer_subs(
+                &chain_id,
+                std::slice::from_ref(sub),
+                format!(
+                    "gas price {current_gas_price} exceeds the subscription's max gas price {}",
+                    sub.max_gas_price.clone().unwrap_or_default()
+                ),
+            );
+        }
+        subscriptions = affordable;
+        if subscriptions.is_empty() {
+            break;
+        }
+
+        let calls = get_calls_from_subs(&subscriptions).await?;
+
+        let calls = match reserve_funds(
+            &w3,
+            &chain_id,
+            &mut subscriptions,
+            calls,
+            &nat::from_u256(&gas_price),
+            &fee,
+            &token_rates,
+        )
+        .await
+        {
+            Ok(calls) => calls,
+            Err(e) => return Err(PublishOnChainError::ChainError(e)),
+        };
+        if subscriptions.is_empty() {
+            log!(
+                "[{PUBLISHER}] chain: {}, no subscriptions with sufficient available balance",
+                chain_id
+            );
+            break;
+        }
+
+        log!("[{PUBLISHER}] Calls inited, chain: {}", chain_id);
+
+        let multicall_results = match multicall(&w3, &chain_id, calls.clone(), gas_price)
             .await
             .context(PythiaError::UnableToExecuteMulticall)
-            .map_err(PublishOnChainError::ChainError)?;
+        {
+            Ok(multicall_results) => multicall_results,
+            Err(e) => {
+                release_funds(&chain_id, &subscriptions);
+                return Err(PublishOnChainError::ChainError(e));
+            }
+        };
 
         if multicall_results.is_empty() {
             log!(
                 "[{PUBLISHER}] chain: {}, no results from multicall, corruption detected",
                 chain_id
             );
+            release_funds(&chain_id, &subscriptions);
             continue;
         }
 
+        if subscriptions.len() > multicall_results.len() {
+            release_funds(&chain_id, &subscriptions[multicall_results.len()..]);
+        }
+
         let mut remaining_subs = vec![];
 
         for (result, sub) in multicall_results.iter().zip(subscriptions) {
@@ -199,6 +286,7 @@ async fn publish_on_chain(
 
             #[allow(clippy::cmp_owned)]
             if used_gas == Nat::from(0) {
+                release_funds(&chain_id, std::slice::from_ref(&sub));
                 remaining_subs.push(sub);
                 continue;
             }
@@ -209,7 +297,8 @@ async fn publish_on_chain(
                     chain_id,
                     sub.id
                 );
-                Subscriptions::stop(&chain_id, &sub.owner, &sub.id).expect("should stop sub");
+                Subscriptions::stop(&chain_id, &Actor::owner(&sub.owner), &sub.id)
+                    .expect("should stop sub");
                 // inscrease gas limit by 30 persent
                 let new_gas_limit = (used_gas.clone() / 10) * 13;
                 Subscriptions::update(
@@ -219,21 +308,56 @@ async fn publish_on_chain(
                         gas_limit: Some(new_gas_limit),
                         ..Default::default()
                     },
-                    &sub.owner,
+                    &Actor::owner(&sub.owner),
                 )
                 .await
                 .expect("should update sub");
             }
 
             Subscriptions::update_last_update(&chain_id, &sub.id, !result.success, publishing_time);
-            let gas_for_tx = (web3::TRANSFER_GAS_LIMIT / multicall_results.len() as u64) + 100;
-            used_gas += gas_for_tx;
-
-            let mut amount = nat::from_u256(&gas_price) * (used_gas);
-            amount += fee.clone();
+            used_gas += transfer_gas_share(multicall_results.len());
+
+            let amount = nat::from_u256(&gas_price) * (used_gas) + nat::from_u256(&result.l1_fee);
+
+            // the reservation is replaced by the actual costs
+            release_funds(&chain_id, std::slice::from_ref(&sub));
+
+            if let Err(e) = Balances::charge(
+                &chain_id,
+                &sub.owner,
+                &amount,
+                &token_rates,
+                LedgerEntryKind::ExecutionCharge {
+                    subscription_id: sub.id.clone(),
+                    tx_hash: result.tx_hash.map(|tx_hash| format!("{tx_hash:?}")),
+                },
+            ) {
+                log!(
+                    "[{PUBLISHER}] chain: {}, sub: {}, unable to charge the execution: {e:?}",
+                    chain_id,
+                    sub.id
+                );
+            }
 
-            Balances::reduce(&chain_id, &sub.owner, &amount).expect("should reduce balance");
-            canister::collect_fee(&chain_id, &pma, &fee).expect("should collect fee");
+            // the fee is collected only if the user paid it, in the amount of the user's tier
+            let tier_fee = whitelist::fee(&sub.owner, &fee);
+            match Balances::charge(
+                &chain_id,
+                &sub.owner,
+                &tier_fee,
+                &token_rates,
+                LedgerEntryKind::PlatformFee {
+                    subscription_id: sub.id.clone(),
+                },
+            ) {
+                Ok(_) => canister::collect_fee(&chain_id, &pma, &tier_fee, &sub.id)
+                    .expect("should collect fee"),
+                Err(e) => log!(
+                    "[{PUBLISHER}] chain: {}, sub: {}, unable to charge the fee: {e:?}",
+                    chain_id,
+                    sub.id
+                ),
+            }
         }
 
         subscriptions = remaining_subs;
@@ -243,6 +367,108 @@ async fn publish_on_chain(
     Ok(())
 }
 
+/// Defer the subscriptions until the gas price falls, they are not counted as failed
+fn defer_subs(chain_id: &Nat, subscriptions: &[Subscription], reason: String) {
+    for sub in subscriptions {
+        log!(
+            "[{PUBLISHER}] chain: {}, sub: {}, deferred: {reason}",
+            chain_id,
+            sub.id
+        );
+        metrics!(inc DEFERRED_SUBSCRIPTIONS, chain_id);
+
+        // the publishable subscription keeps the execution condition it had before the check
+        if let Err(e) = Subscriptions::defer(
+            chain_id,
+            &sub.id,
+            sub.method.exec_condition.clone(),
+            reason.clone(),
+        ) {
+            log!(
+                "[{PUBLISHER}] chain: {}, sub: {}, unable to defer: {e:?}",
+                chain_id,
+                sub.id
+            );
+        }
+    }
+}
+
+/// Share of the transfer gas charged to every subscription of a batch with `batch_len` results
+fn transfer_gas_share(batch_len: usize) -> u64 {
+    (web3::TRANSFER_GAS_LIMIT / batch_len.max(1) as u64) + 100
+}
+
+/// Reserve the maximum execution cost for every subscription, the ones whose available
+/// balance can't cover it are excluded from the batch with their calls. On rollups the cost
+/// includes the share of the estimated L1 data fee, the share grows when subscriptions are
+/// excluded, so it's estimated again. Returns the calls of the remaining subscriptions
+async fn reserve_funds<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    subscriptions: &mut Vec<Subscription>,
+    mut calls: Vec<Call>,
+    gas_price: &Nat,
+    fee: &Nat,
+    token_rates: &HashMap<String, TokenRate>,
+) -> Result<Vec<Call>> {
+    while !subscriptions.is_empty() {
+        let l1_fees = match estimate_l1_fees(w3, chain_id, &calls).await {
+            Ok(l1_fees) => l1_fees,
+            Err(e) => {
+                release_funds(chain_id, subscriptions);
+                return Err(e);
+            }
+        };
+
+        let count = subscriptions.len();
+        let (reserved, reserved_calls): (Vec<_>, Vec<_>) = subscriptions
+            .drain(..)
+            .zip(calls)
+            .zip(l1_fees)
+            .filter(|((sub, _), l1_fee)| {
+                // the share of the transfer gas is the largest when the subscription is alone in the batch
+                let max_gas = sub.method.gas_limit.clone() + transfer_gas_share(1);
+                // the L1 fee is estimated without the transaction envelope
+                let max_l1_fee = (nat::from_u256(l1_fee) / 10) * 12;
+                let max_cost =
+                    max_gas * gas_price.clone() + max_l1_fee + whitelist::fee(&sub.owner, fee);
+                match Balances::reserve(chain_id, &sub.owner, &sub.id, &max_cost, token_rates) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log!(
+                            "[{PUBLISHER}] chain: {}, sub: {}, excluded from the batch: {e:?}",
+                            chain_id,
+                            sub.id
+                        );
+                        false
+                    }
+                }
+            })
+            .map(|(sub_call, _)| sub_call)
+            .unzip();
+        *subscriptions = reserved;
+        calls = reserved_calls;
+
+        if subscriptions.len() == count {
+            break;
+        }
+    }
+
+    Ok(calls)
+}
+
+fn release_funds(chain_id: &Nat, subscriptions: &[Subscription]) {
+    for sub in subscriptions {
+        if let Err(e) = Balances::release(chain_id, &sub.owner, &sub.id) {
+            log!(
+                "[{PUBLISHER}] chain: {}, sub: {}, unable to release the reservation: {e:?}",
+                chain_id,
+                sub.id
+            );
+        }
+    }
+}
+
 async fn get_calls_from_subs(subs: &[Subscription]) -> Result<Vec<Call>, PublishOnChainError> {
     let mut calls = Vec::with_capacity(subs.len());
 
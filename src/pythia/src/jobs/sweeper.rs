@@ -0,0 +1,250 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use candid::Nat;
+use futures::future::join_all;
+use ic_web3_rs::types::H256;
+
+use crate::{
+    log, metrics, retry_until_success,
+    types::{
+        balance::Balances,
+        chains::Chains,
+        deposit_addresses::{DepositAddresses, SweepTx, Sweeps},
+        errors::PythiaError,
+        ledger::LedgerEntryKind,
+        logger::SWEEPER,
+    },
+    utils::{
+        address, canister,
+        multicall::{self, Transfer, BASE_GAS, GAS_PER_TRANSFER},
+        nat, web3,
+    },
+};
+
+/// Seconds between the sweep jobs, every run checks every deposit address on every chain
+pub const SWEEP_INTERVAL: u64 = 60 * 30;
+const MAX_SWEEPS: usize = 20;
+const SWEEP_GAS_LIMIT: u64 = BASE_GAS + GAS_PER_TRANSFER;
+const TX_TIMEOUT: u64 = 60 * 5;
+
+pub fn execute() {
+    ic_cdk::spawn(sweep())
+}
+
+/// Move funds from the users' deposit addresses to the PMA and credit the users' balances.
+/// Paused and decommissioning chains are skipped
+pub async fn sweep() {
+    log!("[{SWEEPER}] sweep job started");
+    let deposit_addresses = DepositAddresses::get_all();
+    if deposit_addresses.is_empty() {
+        return;
+    }
+
+    for chain in Chains::get_all() {
+        if chain.is_paused() || chain.decommission.is_some() {
+            continue;
+        }
+
+        let gas_price = match web3::gas_price(&chain.chain_id).await {
+            Ok(gas_price) => gas_price,
+            Err(err) => {
+                log!(
+                    "[{SWEEPER}] failed to get the gas price: chain_id = {}, err = {err:?}",
+                    chain.chain_id
+                );
+                continue;
+            }
+        };
+
+        for batch in deposit_addresses.chunks(MAX_SWEEPS) {
+            let futures = batch
+                .iter()
+                .map(|(address, deposit_address)| {
+                    sweep_address(&chain.chain_id, address, deposit_address, &gas_price)
+                })
+                .collect::<Vec<_>>();
+
+            join_all(futures)
+                .await
+                .into_iter()
+                .zip(batch)
+                .for_each(|(result, (address, _))| {
+                    if let Err(err) = result {
+                        log!(
+                            "[{SWEEPER}] failed to sweep: chain_id = {}, address = {}, err = {err:?}",
+                            chain.chain_id,
+                            address
+                        );
+                    }
+                });
+        }
+    }
+
+    log!("[{SWEEPER}] sweep job executed");
+}
+
+/// Sweep the deposit address of the user, a sweep sent by the previous run is settled instead.
+/// The sent transaction is recorded before waiting for it, so a sweep that isn't confirmed in time
+/// is credited by a later run
+pub async fn sweep_address(
+    chain_id: &Nat,
+    address: &str,
+    deposit_address: &str,
+    gas_price: &Nat,
+) -> Result<()> {
+    if let Some(tx) = Sweeps::lock(chain_id, address)? {
+        return settle(chain_id, address, deposit_address, &tx).await;
+    }
+
+    let tx = match send(chain_id, address, deposit_address, gas_price).await {
+        Ok(Some(tx)) => tx,
+        Ok(None) => {
+            Sweeps::unlock(chain_id, address);
+            return Ok(());
+        }
+        Err(err) => {
+            Sweeps::unlock(chain_id, address);
+            return Err(err);
+        }
+    };
+
+    let w3 = web3::instance(chain_id)?;
+    if let Err(err) =
+        web3::wait_for_success_confirmation(&w3, &H256::from_str(&tx.tx_hash)?, TX_TIMEOUT).await
+    {
+        log!(
+            "[{SWEEPER}] sweep is not confirmed, will be checked on the next run: chain_id = {}, address = {}, err = {err:?}",
+            chain_id,
+            address
+        );
+    }
+
+    settle(chain_id, address, deposit_address, &tx).await
+}
+
+/// Send the balance of the deposit address to the PMA, returns `None` if it can't pay for gas
+async fn send(
+    chain_id: &Nat,
+    address: &str,
+    deposit_address: &str,
+    gas_price: &Nat,
+) -> Result<Option<SweepTx>> {
+    let w3 = web3::instance(chain_id)?;
+
+    metrics!(inc RPC_OUTCALLS, "balance");
+    let balance = retry_until_success!(w3.eth().balance(
+        address::to_h160(deposit_address)?,
+        None,
+        canister::transform_ctx()
+    ))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "balance");
+
+    let gas_reserve = nat::to_u256(&(Nat::from(SWEEP_GAS_LIMIT) * gas_price.clone()));
+    if balance <= gas_reserve * 2 {
+        return Ok(None);
+    }
+
+    let pma = canister::pma_h160()
+        .await
+        .context(PythiaError::UnableToGetPMA)?;
+    let gas = multicall::estimate_multitransfer(
+        &w3,
+        chain_id,
+        &[Transfer {
+            target: pma,
+            value: balance - gas_reserve,
+        }],
+        deposit_address,
+    )
+    .await?;
+    // the gas is paid from the deposit address on top of the transferred value
+    if balance <= gas.cost() * 2 {
+        return Ok(None);
+    }
+
+    let value = balance - gas.cost();
+    let (tx_hash, nonce) = multicall::send_multitransfer_from(
+        &w3,
+        chain_id,
+        vec![Transfer { target: pma, value }],
+        deposit_address.to_string(),
+        web3::key_info_with_path(canister::deposit_derivation_path(address)?),
+        &gas,
+    )
+    .await
+    .context(PythiaError::UnableToTransferFunds)?;
+
+    let tx = SweepTx {
+        tx_hash: format!("{tx_hash:?}"),
+        nonce: nat::from_u256(&nonce),
+        value: nat::from_u256(&value),
+    };
+    Sweeps::set_sent(chain_id, address, tx.clone());
+
+    Ok(Some(tx))
+}
+
+/// Credit the user's balance once the sweep is confirmed. The deposit address is used only by the sweeper,
+/// so its nonce consumed without the receipt of the sweep means that the sweep was dropped
+async fn settle(chain_id: &Nat, address: &str, deposit_address: &str, tx: &SweepTx) -> Result<()> {
+    let tx_hash = H256::from_str(&tx.tx_hash)?;
+    let receipt = match web3::get_receipt(chain_id, &tx_hash).await? {
+        Some(receipt) => Some(receipt),
+        None => {
+            let nonce = web3::transaction_count(chain_id, deposit_address).await?;
+            if nat::from_u256(&nonce) <= tx.nonce {
+                log!(
+                    "[{SWEEPER}] sweep is not executed yet: chain_id = {}, address = {}, tx_hash = {}",
+                    chain_id,
+                    address,
+                    tx.tx_hash
+                );
+                return Ok(());
+            }
+
+            // the receipt could appear after it was requested
+            web3::get_receipt(chain_id, &tx_hash).await?
+        }
+    };
+
+    // the sweep could be settled by a concurrent call while the receipt was requested
+    if !Sweeps::finish(chain_id, address, &tx.tx_hash) {
+        return Ok(());
+    }
+
+    match receipt {
+        Some(receipt) if web3::is_successful(&receipt) => {}
+        _ => {
+            log!(
+                "[{SWEEPER}] sweep failed or was dropped: chain_id = {}, address = {}, tx_hash = {}",
+                chain_id,
+                address,
+                tx.tx_hash
+            );
+            return Ok(());
+        }
+    }
+
+    if !Balances::is_exists(chain_id, address)? {
+        Balances::create(chain_id, address).context(PythiaError::UnableToAddNewBalance)?;
+    }
+
+    Balances::add_amount(
+        chain_id,
+        address,
+        &tx.value,
+        LedgerEntryKind::Deposit {
+            tx_hash: Some(tx.tx_hash.clone()),
+        },
+    )
+    .context(PythiaError::UnableToIncreaseBalance)?;
+
+    log!(
+        "[{SWEEPER}] swept: chain_id = {}, address = {}, amount = {}",
+        chain_id,
+        address,
+        tx.value
+    );
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use crate::{
+    log,
+    types::{logger::WHITELIST, subscription::Subscriptions, whitelist},
+};
+
+/// Remove the expired whitelist entries and stop the subscriptions of their addresses
+pub fn expire() {
+    for address in whitelist::remove_expired() {
+        match Subscriptions::stop_all(None, vec![], Some(address.clone())) {
+            Ok(stopped) => log!(
+                "[{WHITELIST}] subscriptions of the expired address stopped: address = {address}, ids = {stopped:?}"
+            ),
+            Err(e) => log!(
+                "[{WHITELIST}] unable to stop subscriptions of the expired address: address = {address}, err = {e:?}"
+            ),
+        }
+    }
+}
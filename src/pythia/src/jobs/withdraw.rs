@@ -1,20 +1,38 @@
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use candid::Nat;
-use ic_web3_rs::types::H160;
+use ic_web3_rs::{
+    types::{TransactionReceipt, H256, U256},
+    Transport, Web3,
+};
+use itertools::Itertools;
 
+use super::decommission;
 use crate::{
     clone_with_state, log,
     types::{
+        balance::Balances,
+        chains::Chains,
         errors::PythiaError,
+        ledger::LedgerEntryKind,
         logger::WITHDRAWER,
-        withdraw::{WithdrawRequest, WithdrawRequests},
+        withdraw::{
+            WithdrawRequest, WithdrawRequests, WithdrawStatus, MAX_WITHDRAW_ATTEMPTS,
+            WITHDRAW_REQUEST_RETENTION,
+        },
+    },
+    utils::{
+        address, canister, erc20,
+        multicall::{self, MultitransferGas, Transfer},
+        nat, time, web3,
     },
-    utils::{multicall, multicall::Transfer, nat, web3},
 };
 
 const MAX_TRANSFERS: usize = 100;
+/// Tokens are transferred by the PMA directly, a transaction per request
+const MAX_TOKEN_TRANSFERS: usize = 1;
+const TX_TIMEOUT: u64 = 60 * 5;
 
 pub fn execute() {
     ic_cdk::spawn(withdraw())
@@ -23,39 +41,430 @@ pub fn execute() {
 pub async fn withdraw() {
     log!("[{WITHDRAWER}] withdraw job started");
     for (chain_id, reqs) in clone_with_state!(withdraw_requests).0 {
+        if let Err(err) = check_sent(&chain_id, &reqs).await {
+            log!("[{WITHDRAWER}] failed to check sent withdrawals: {err:?}");
+        }
+
+        let reqs = abandon_failed(&chain_id, reqs);
         if let Err(err) = send_funds(&chain_id, &reqs).await {
-            log!("failed to send funds: {err:?}");
-            continue;
+            log!("[{WITHDRAWER}] failed to send funds: {err:?}");
+        }
+
+        let before = time::in_seconds().saturating_sub(WITHDRAW_REQUEST_RETENTION);
+        if let Err(err) = WithdrawRequests::prune(&chain_id, before) {
+            log!("[{WITHDRAWER}] failed to prune withdrawals: {err:?}");
         }
 
-        WithdrawRequests::erase(&chain_id).expect("should erase withdraw requests");
+        if Chains::is_decommissioning(&chain_id) {
+            if let Err(err) = decommission::decommission(&chain_id).await {
+                log!("[{WITHDRAWER}] failed to decommission the chain {chain_id}: {err:?}");
+            }
+        }
     }
 
     log!("[{WITHDRAWER}] withdraw job executed");
 }
 
+/// Confirm the requests which transactions were sent, but not confirmed during the previous runs.
+/// A transaction is considered dropped only when the PMA nonce it used is consumed by another transaction,
+/// so its requests are never sent twice
+async fn check_sent(chain_id: &Nat, reqs: &[WithdrawRequest]) -> Result<()> {
+    let sent = reqs
+        .iter()
+        .filter_map(|req| match &req.status {
+            WithdrawStatus::Sent { tx_hash, nonce } => {
+                Some(((tx_hash.clone(), nonce.clone()), req))
+            }
+            _ => None,
+        })
+        .into_group_map();
+    if sent.is_empty() {
+        return Ok(());
+    }
+
+    let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
+    for ((tx_hash, nonce), reqs) in sent {
+        let tx_hash = H256::from_str(&tx_hash)?;
+        if let Some(receipt) = web3::get_receipt(chain_id, &tx_hash).await? {
+            confirm(chain_id, &reqs, &receipt, &tx_hash)?;
+            continue;
+        }
+
+        // transactions sent before the nonce was recorded are waited for until they are executed
+        let is_nonce_consumed = match &nonce {
+            Some(nonce) => nat::from_u256(&web3::transaction_count(chain_id, &pma).await?) > *nonce,
+            None => false,
+        };
+        if !is_nonce_consumed {
+            log!(
+                "[{WITHDRAWER}] chain: {}, tx {:?} is not executed yet",
+                chain_id,
+                tx_hash
+            );
+            continue;
+        }
+
+        // the receipt could appear after the nonce was requested
+        if let Some(receipt) = web3::get_receipt(chain_id, &tx_hash).await? {
+            confirm(chain_id, &reqs, &receipt, &tx_hash)?;
+            continue;
+        }
+
+        // the nonce is used by another transaction, so the requests of the dropped one are sent again
+        WithdrawRequests::update_status(
+            chain_id,
+            &reqs.iter().map(|req| req.id.clone()).collect::<Vec<_>>(),
+            WithdrawStatus::Failed {
+                reason: format!("tx {tx_hash:?} was dropped"),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Abandon the failed requests that ran out of attempts and return their charged amounts
+/// to the owners, returns the rest of the requests
+fn abandon_failed(chain_id: &Nat, reqs: Vec<WithdrawRequest>) -> Vec<WithdrawRequest> {
+    let (exhausted, reqs): (Vec<_>, Vec<_>) = reqs.into_iter().partition(|req| {
+        matches!(req.status, WithdrawStatus::Failed { .. }) && req.attempts >= MAX_WITHDRAW_ATTEMPTS
+    });
+
+    for req in exhausted {
+        let WithdrawStatus::Failed { reason } = req.status else {
+            continue;
+        };
+
+        let result = WithdrawRequests::abandon(chain_id, &req.id, reason).and_then(|req| {
+            let kind = LedgerEntryKind::WithdrawalCancelled {
+                withdrawal_id: req.id.clone(),
+            };
+            match &req.token {
+                Some(token) => {
+                    Balances::add_token_amount(chain_id, &req.owner, token, &req.charged, kind)
+                }
+                None => Balances::add_amount(chain_id, &req.owner, &req.charged, kind),
+            }
+        });
+
+        if let Err(err) = result {
+            log!(
+                "[{WITHDRAWER}] chain: {}, failed to abandon withdrawal {}: {err:?}",
+                chain_id,
+                req.id
+            );
+        }
+    }
+
+    reqs
+}
+
 async fn send_funds(chain_id: &Nat, reqs: &[WithdrawRequest]) -> Result<()> {
-    if reqs.is_empty() {
+    let unsent = reqs
+        .iter()
+        .filter(|req| req.status.is_unsent())
+        .collect::<Vec<_>>();
+    if unsent.is_empty() {
         return Ok(());
     }
 
-    let transfers: Vec<Transfer> = reqs
+    let w3 = web3::instance(chain_id)?;
+    for (token, reqs) in unsent
+        .into_iter()
+        .into_group_map_by(|req| req.token.clone())
+    {
+        let chunk_size = match token {
+            Some(_) => MAX_TOKEN_TRANSFERS,
+            None => MAX_TRANSFERS,
+        };
+
+        for reqs_chunk in reqs.chunks(chunk_size) {
+            send_chunk(&w3, chain_id, &token, reqs_chunk).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the chunk of requests in a single transaction, the status of every request
+/// is updated as soon as it's known, so only unsent requests are retried
+async fn send_chunk<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    token: &Option<String>,
+    reqs: &[&WithdrawRequest],
+) -> Result<()> {
+    // requests could be cancelled while the job was waiting for other transactions
+    let ids = WithdrawRequests::start_sending(
+        chain_id,
+        &reqs.iter().map(|req| req.id.clone()).collect::<Vec<_>>(),
+    )?;
+    // a request with an invalid receiver fails alone, the rest of the chunk is sent
+    let (mut reqs, invalid): (Vec<_>, Vec<_>) = reqs
         .iter()
-        .map(|req| Transfer {
-            target: H160::from_str(&req.receiver).expect("should be valid address"),
-            value: nat::to_u256(&req.amount),
+        .filter(|req| ids.contains(&req.id))
+        .map(|req| (*req).clone())
+        .partition(|req| address::to_h160(&req.receiver).is_ok());
+    if !invalid.is_empty() {
+        WithdrawRequests::update_status(
+            chain_id,
+            &invalid.iter().map(|req| req.id.clone()).collect::<Vec<_>>(),
+            WithdrawStatus::Failed {
+                reason: PythiaError::InvalidAddressFormat.to_string(),
+            },
+        )?;
+    }
+    if reqs.is_empty() {
+        return Ok(());
+    }
+
+    let result = match token {
+        Some(token) => send_token(w3, chain_id, token, &reqs).await,
+        None => match split_gas(w3, chain_id, &mut reqs).await {
+            Ok(Some(gas)) => match to_transfers(&reqs, |req| &req.amount) {
+                Ok(transfers) => multicall::send_multitransfer(w3, chain_id, transfers, &gas).await,
+                Err(err) => Err(err),
+            },
+            Ok(None) => return Ok(()),
+            Err(err) => Err(err),
+        },
+    }
+    .context(PythiaError::UnableToTransferFunds);
+
+    // requests that can't pay for gas are already marked as failed
+    let ids = reqs.iter().map(|req| req.id.clone()).collect::<Vec<_>>();
+
+    let (tx_hash, nonce) = match result {
+        Ok(sent) => sent,
+        Err(err) => {
+            log!("[{WITHDRAWER}] failed to send funds: {err:?}");
+            return WithdrawRequests::update_status(
+                chain_id,
+                &ids,
+                WithdrawStatus::Failed {
+                    reason: format!("{err:?}"),
+                },
+            );
+        }
+    };
+
+    WithdrawRequests::update_status(
+        chain_id,
+        &ids,
+        WithdrawStatus::Sent {
+            tx_hash: format!("{tx_hash:?}"),
+            nonce: Some(nat::from_u256(&nonce)),
+        },
+    )?;
+
+    match web3::wait_for_success_confirmation(w3, &tx_hash, TX_TIMEOUT).await {
+        Ok(receipt) => confirm(
+            chain_id,
+            &reqs.iter().collect::<Vec<_>>(),
+            &receipt,
+            &tx_hash,
+        ),
+        Err(err) => {
+            log!(
+                "[{WITHDRAWER}] chain: {}, tx {:?} is not confirmed, will be checked on the next run: {err:?}",
+                chain_id,
+                tx_hash
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Transfer the token to the receiver of the only request, returns the transaction hash and the PMA nonce
+async fn send_token<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    token: &str,
+    reqs: &[WithdrawRequest],
+) -> Result<(H256, U256)> {
+    match to_transfers(reqs, |req| &req.amount)?.as_slice() {
+        [transfer] => {
+            erc20::transfer(
+                w3,
+                chain_id,
+                address::to_h160(token)?,
+                transfer.target,
+                transfer.value,
+            )
+            .await
+        }
+        _ => Err(anyhow!("tokens are transferred a request per transaction")),
+    }
+}
+
+fn to_transfers(
+    reqs: &[WithdrawRequest],
+    value: impl Fn(&WithdrawRequest) -> &Nat,
+) -> Result<Vec<Transfer>> {
+    reqs.iter()
+        .map(|req| {
+            Ok(Transfer {
+                target: address::to_h160(&req.receiver)?,
+                value: nat::to_u256(value(req)),
+            })
         })
-        .collect();
+        .collect()
+}
+
+/// Split the estimated gas of the native multitransfer evenly between the requests, the share is paid
+/// from the charged amount. Requests that can't cover their share are marked as failed and removed from `reqs`,
+/// returns `None` if no request is left
+async fn split_gas<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    reqs: &mut Vec<WithdrawRequest>,
+) -> Result<Option<MultitransferGas>> {
+    let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
 
-    for transfers_chunk in transfers.chunks(MAX_TRANSFERS) {
-        multicall::multitransfer(
-            &web3::instance(chain_id)?,
+    while !reqs.is_empty() {
+        let transfers = to_transfers(reqs, |req| &req.charged)?;
+        let gas = multicall::estimate_multitransfer(w3, chain_id, &transfers, &pma).await?;
+        let share = nat::from_u256(&gas.cost_per_transfer(reqs.len()));
+
+        let (payable, unpayable): (Vec<_>, Vec<_>) =
+            reqs.drain(..).partition(|req| req.charged > share);
+        *reqs = payable;
+
+        if unpayable.is_empty() {
+            let fees = reqs
+                .iter_mut()
+                .map(|req| {
+                    req.amount = req.charged.clone() - share.clone();
+                    req.fee = share.clone();
+                    (req.id.clone(), share.clone())
+                })
+                .collect::<HashMap<_, _>>();
+            WithdrawRequests::set_fees(chain_id, &fees)?;
+
+            return Ok(Some(gas));
+        }
+
+        // the share is recalculated for the rest of the requests
+        WithdrawRequests::update_status(
             chain_id,
-            transfers_chunk.to_vec(),
-        )
-        .await
-        .context(PythiaError::UnableToTransferFunds)?;
+            &unpayable
+                .iter()
+                .map(|req| req.id.clone())
+                .collect::<Vec<_>>(),
+            WithdrawStatus::Failed {
+                reason: format!("not enough funds to pay for gas, required: {share}"),
+            },
+        )?;
+    }
+
+    Ok(None)
+}
+
+/// Mark the requests sent in the transaction as confirmed, or as failed if the funds didn't reach the receiver
+fn confirm(
+    chain_id: &Nat,
+    reqs: &[&WithdrawRequest],
+    receipt: &TransactionReceipt,
+    tx_hash: &H256,
+) -> Result<()> {
+    let tx_hash = format!("{tx_hash:?}");
+    let (confirmed, failed): (Vec<_>, Vec<_>) = if !web3::is_successful(receipt) {
+        (vec![], reqs.iter().collect())
+    } else {
+        let mut expected: HashMap<(Option<String>, String), Nat> = HashMap::new();
+        for req in reqs {
+            *expected
+                .entry((req.token.clone(), req.receiver.clone()))
+                .or_default() += req.amount.clone();
+        }
+
+        // a token transfer can succeed without moving the funds, e.g. for a fee-on-transfer token
+        let mut is_transferred = HashMap::new();
+        for ((token, receiver), amount) in expected {
+            let transferred = match &token {
+                Some(token) => {
+                    erc20::transferred_to(
+                        receipt,
+                        &address::to_h160(token)?,
+                        &address::to_h160(&receiver)?,
+                    )? >= amount
+                }
+                None => true,
+            };
+            is_transferred.insert((token, receiver), transferred);
+        }
+
+        reqs.iter()
+            .partition(|req| is_transferred[&(req.token.clone(), req.receiver.clone())])
+    };
+
+    if !confirmed.is_empty() {
+        WithdrawRequests::update_status(
+            chain_id,
+            &confirmed
+                .iter()
+                .map(|req| req.id.clone())
+                .collect::<Vec<_>>(),
+            WithdrawStatus::Confirmed {
+                tx_hash: tx_hash.clone(),
+            },
+        )?;
+
+        refund(chain_id, reqs.len(), &confirmed, receipt);
+    }
+
+    if !failed.is_empty() {
+        WithdrawRequests::update_status(
+            chain_id,
+            &failed.iter().map(|req| req.id.clone()).collect::<Vec<_>>(),
+            WithdrawStatus::Failed {
+                reason: format!("funds were not transferred in {tx_hash}"),
+            },
+        )?;
     }
 
     Ok(())
 }
+
+/// Return the difference between the estimated and the spent gas of the native transfers,
+/// the spent gas is split evenly between all the `reqs_count` requests of the transaction
+fn refund(
+    chain_id: &Nat,
+    reqs_count: usize,
+    confirmed: &[&&WithdrawRequest],
+    receipt: &TransactionReceipt,
+) {
+    let (Some(gas_used), Some(gas_price)) = (receipt.gas_used, receipt.effective_gas_price) else {
+        log!(
+            "[{WITHDRAWER}] chain: {}, receipt has no gas info, no refund",
+            chain_id
+        );
+        return;
+    };
+    let spent = nat::from_u256(&(gas_used * gas_price)) / Nat::from(reqs_count.max(1));
+
+    for req in confirmed.iter().filter(|req| req.token.is_none()) {
+        if req.fee <= spent {
+            continue;
+        }
+
+        let refund = req.fee.clone() - spent.clone();
+        let result = Balances::add_amount(
+            chain_id,
+            &req.owner,
+            &refund,
+            LedgerEntryKind::WithdrawalRefund {
+                withdrawal_id: req.id.clone(),
+            },
+        )
+        .and_then(|_| WithdrawRequests::refund(chain_id, &req.id, &refund));
+
+        if let Err(err) = result {
+            log!(
+                "[{WITHDRAWER}] chain: {}, failed to refund withdrawal {}: {err:?}",
+                chain_id,
+                req.id
+            );
+        }
+    }
+}
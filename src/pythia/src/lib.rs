@@ -4,12 +4,15 @@ mod migrations;
 mod types;
 mod utils;
 
-use std::cell::RefCell;
+use std::{cell::RefCell, time::Duration};
 
 use candid::{Nat, Principal};
 use ic_web3_rs::transforms::processors;
 use ic_web3_rs::transforms::transform::TransformProcessor;
-use types::{chains::Chain, errors::PythiaError, state::State, timer::Timer};
+use types::{
+    chains::Chain, errors::PythiaError, runway::DEFAULT_RUNWAY_WARNING_THRESHOLD, state::State,
+    timer::Timer,
+};
 
 use ic_cdk::{
     api::management_canister::http_request::{HttpResponse, TransformArgs},
@@ -52,6 +55,12 @@ fn init(tx_fee: Nat, key_name: String, sybil_canister: Principal) {
         state.subs_limit_wallet = 5.into();
         state.subs_limit_total = 100.into();
         state.timer_frequency = (5 * 60).into();
+        state.runway_warning_threshold = DEFAULT_RUNWAY_WARNING_THRESHOLD.into();
         state.timer = Some(Timer::default());
-    })
+    });
+
+    ic_cdk_timers::set_timer_interval(
+        Duration::from_secs(jobs::sweeper::SWEEP_INTERVAL),
+        jobs::sweeper::execute,
+    );
 }
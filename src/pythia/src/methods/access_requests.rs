@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use candid::Nat;
+use ic_cdk::{query, update};
+
+use crate::{
+    log,
+    types::{
+        access_requests::{AccessRequest, AccessRequestStatus, AccessRequests},
+        errors::PythiaError,
+        pagination::{Pagination, PaginationResult},
+        roles::Role,
+        siwe::SiweAction,
+        whitelist::{self, WhitelistEntryRequest},
+    },
+    utils::{address, siwe, validator},
+};
+
+/// Apply for the whitelist with the signer address
+///
+/// # Arguments
+///
+/// * `msg` - SIWE message, it must list `urn:pythia:request_access` in its resources
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `contact` - How to reach the applicant, e.g. an email or a telegram handle
+/// * `note` - What the applicant is going to use the service for, can be omitted
+///
+/// # Returns
+///
+/// Returns a result with the pending application
+#[update]
+pub async fn request_access(
+    msg: String,
+    sig: String,
+    contact: String,
+    note: Option<String>,
+) -> Result<AccessRequest, String> {
+    _request_access(msg, sig, contact, note)
+        .await
+        .map_err(|e| format!("failed to request access: {e:?}"))
+}
+
+#[inline]
+async fn _request_access(
+    msg: String,
+    sig: String,
+    contact: String,
+    note: Option<String>,
+) -> Result<AccessRequest> {
+    let address = siwe::siwe_authorize_unbound(&msg, &sig, SiweAction::RequestAccess)
+        .await
+        .map_err(PythiaError::from)?;
+
+    AccessRequests::add(&address, &contact, note, ic_cdk::caller())
+}
+
+/// Get the status of the latest application of an address
+///
+/// # Arguments
+///
+/// * `address` - Address of the applicant
+///
+/// # Returns
+///
+/// Returns a result with the status of the application
+#[query]
+pub fn get_access_request_status(address: String) -> Result<AccessRequestStatus, String> {
+    _get_access_request_status(address)
+        .map_err(|e| format!("failed to get the access request status: {e:?}"))
+}
+
+#[inline]
+fn _get_access_request_status(address: String) -> Result<AccessRequestStatus> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+
+    Ok(AccessRequests::get(&address)?.status)
+}
+
+/// Get the applications for the whitelist
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `status` - Return only the applications with the status, can be omitted
+/// * `pagination` - Pagination options, can be omitted. Applications are sorted by creation time
+///
+/// # Returns
+///
+/// Returns a result with the applications
+#[query]
+pub fn get_access_requests(
+    status: Option<AccessRequestStatus>,
+    pagination: Option<Pagination>,
+) -> Result<PaginationResult<AccessRequest>, String> {
+    _get_access_requests(status, pagination)
+        .map_err(|e| format!("failed to get access requests: {e:?}"))
+}
+
+#[inline]
+fn _get_access_requests(
+    status: Option<AccessRequestStatus>,
+    pagination: Option<Pagination>,
+) -> Result<PaginationResult<AccessRequest>> {
+    validator::role(Role::WhitelistManager)?;
+
+    let requests = AccessRequests::get_all(status);
+    Ok(match pagination {
+        Some(pagination) => pagination.paginate(requests),
+        None => requests.into(),
+    })
+}
+
+/// Approve a pending application and add the applicant to the whitelist
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `address` - Address of the applicant
+/// * `tier` - Whitelist tier of the applicant, can be omitted
+/// * `expires_at` - Time in seconds after which the applicant is removed from the whitelist, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn approve_access_request(
+    address: String,
+    tier: Option<String>,
+    expires_at: Option<Nat>,
+) -> Result<(), String> {
+    _approve_access_request(address, tier, expires_at)
+        .map_err(|e| format!("failed to approve the access request: {e:?}"))
+}
+
+#[inline]
+fn _approve_access_request(
+    address: String,
+    tier: Option<String>,
+    expires_at: Option<Nat>,
+) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
+
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    let request = AccessRequests::get(&address)?;
+    if request.status != AccessRequestStatus::Pending {
+        return Err(PythiaError::AccessRequestIsNotPending.into());
+    }
+
+    let entry = WhitelistEntryRequest {
+        address: address.clone(),
+        note: request.note,
+        expires_at,
+        tier,
+    };
+    // an existing entry, e.g. an expired one that isn't removed yet, is not replaced
+    if whitelist::add(&[entry], ic_cdk::caller())?.is_empty() {
+        return Err(PythiaError::AddressIsAlreadyWhitelisted.into());
+    }
+    AccessRequests::review(
+        &address,
+        AccessRequestStatus::Approved,
+        ic_cdk::caller(),
+        None,
+    )?;
+
+    log!("[WHITELIST] access request approved: {address}");
+    Ok(())
+}
+
+/// Reject a pending application
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `address` - Address of the applicant
+/// * `reason` - Reason of the rejection, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn reject_access_request(address: String, reason: Option<String>) -> Result<(), String> {
+    _reject_access_request(address, reason)
+        .map_err(|e| format!("failed to reject the access request: {e:?}"))
+}
+
+#[inline]
+fn _reject_access_request(address: String, reason: Option<String>) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
+
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    AccessRequests::review(
+        &address,
+        AccessRequestStatus::Rejected,
+        ic_cdk::caller(),
+        reason,
+    )?;
+
+    log!("[WHITELIST] access request rejected: {address}");
+    Ok(())
+}
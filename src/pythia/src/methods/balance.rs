@@ -1,15 +1,27 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use candid::Nat;
 use ic_cdk::{query, update};
 
 use crate::{
-    jobs::withdraw,
+    jobs::{sweeper, withdraw},
     log,
+    methods::subscriptions,
     types::{
-        balance::Balances, errors::PythiaError, subscription::Subscriptions, timer::Timer,
-        whitelist, withdraw::WithdrawRequests,
+        balance::Balances,
+        chains::Chains,
+        deposit_addresses::DepositAddresses,
+        errors::PythiaError,
+        ledger::{Ledger, LedgerEntry, LedgerEntryKind},
+        operators::Actor,
+        pagination::{Pagination, PaginationResult},
+        session::Authorization,
+        siwe::SiweAction,
+        subscription::Subscriptions,
+        timer::Timer,
+        whitelist,
+        withdraw::{WithdrawRequest, WithdrawRequests},
     },
-    utils::{address, canister, nat, siwe, web3},
+    utils::{address, canister, erc20, nat, web3},
 };
 
 /// Get the PMA address
@@ -24,6 +36,80 @@ pub async fn get_pma() -> Result<String, String> {
         .map_err(|e| format!("failed to get the PMA: {e:?}"))
 }
 
+/// Get the deposit address of the user, the native coin sent to it is credited to the user's balance automatically.
+/// ERC-20 tokens sent to the deposit address are not credited, they should be deposited to the PMA
+///
+/// # Arguments
+///
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the deposit address
+#[update]
+pub async fn get_deposit_address(address: String) -> Result<String, String> {
+    _get_deposit_address(address)
+        .await
+        .map_err(|e| format!("failed to get the deposit address: {e:?}"))
+}
+
+#[inline]
+async fn _get_deposit_address(address: String) -> Result<String> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    if !whitelist::is_whitelisted(&address) {
+        return Err(PythiaError::UserIsNotWhitelisted.into());
+    }
+
+    canister::deposit_address(&address).await
+}
+
+/// Move funds from the user's deposit address to the PMA without waiting for the sweep job
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `msg` - SIWE message, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn sweep_deposit_address(chain_id: Nat, msg: String, sig: String) -> Result<(), String> {
+    _sweep_deposit_address(chain_id, Authorization::Siwe { msg, sig })
+        .await
+        .map_err(|e| format!("failed to sweep the deposit address: {e:?}"))
+}
+
+/// Move funds from the deposit address of the caller's session to the PMA without waiting for the sweep job
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_sweep_deposit_address(chain_id: Nat) -> Result<(), String> {
+    _sweep_deposit_address(chain_id, Authorization::Session)
+        .await
+        .map_err(|e| format!("failed to sweep the deposit address: {e:?}"))
+}
+
+#[inline]
+async fn _sweep_deposit_address(chain_id: Nat, auth: Authorization) -> Result<()> {
+    let address = auth.address(SiweAction::Deposit, &chain_id).await?;
+    let deposit_address =
+        DepositAddresses::get(&address).context(PythiaError::DepositAddressDoesNotExist)?;
+
+    let gas_price = web3::gas_price(&chain_id)
+        .await
+        .context(PythiaError::UnableToGetGasPrice)?;
+
+    sweeper::sweep_address(&chain_id, &address, &deposit_address, &gas_price).await
+}
+
 /// Deposit amount to the PMA
 ///
 /// # Arguments
@@ -43,16 +129,31 @@ pub async fn deposit(
     msg: String,
     sig: String,
 ) -> Result<(), String> {
-    _deposit(chain_id, tx_hash, msg, sig)
+    _deposit(chain_id, tx_hash, Authorization::Siwe { msg, sig })
         .await
         .map_err(|e| format!("failed to deposit: {e:?}"))
 }
 
-#[inline]
-async fn _deposit(chain_id: Nat, tx_hash: String, msg: String, sig: String) -> Result<()> {
-    let address = siwe::siwe_recover(&msg, &sig)
+/// Deposit amount to the PMA for the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `tx_hash` - 256-bit hash of the transaction, for example 0x1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_deposit(chain_id: Nat, tx_hash: String) -> Result<(), String> {
+    _deposit(chain_id, tx_hash, Authorization::Session)
         .await
-        .context(PythiaError::UnableToRecoverAddress)?;
+        .map_err(|e| format!("failed to deposit: {e:?}"))
+}
+
+#[inline]
+async fn _deposit(chain_id: Nat, tx_hash: String, auth: Authorization) -> Result<()> {
+    let address = auth.address(SiweAction::Deposit, &chain_id).await?;
     if !whitelist::is_whitelisted(&address) {
         return Err(PythiaError::UserIsNotWhitelisted.into());
     }
@@ -60,7 +161,7 @@ async fn _deposit(chain_id: Nat, tx_hash: String, msg: String, sig: String) -> R
         Balances::create(&chain_id, &address).context(PythiaError::UnableToAddNewBalance)?;
     }
 
-    let tx = web3::get_tx(&chain_id, &tx_hash)
+    let (tx, receipt) = web3::get_tx(&chain_id, &tx_hash)
         .await
         .context(PythiaError::UnableToGetTx)?;
 
@@ -69,6 +170,30 @@ async fn _deposit(chain_id: Nat, tx_hash: String, msg: String, sig: String) -> R
         .await
         .context(PythiaError::UnableToGetPMA)?;
 
+    if let Some(token) = Chains::get_token(&chain_id, &address::from_h160(&receiver))? {
+        let amount = erc20::transferred_to(&receipt, &receiver, &pma)?;
+        #[allow(clippy::cmp_owned)]
+        if amount <= Nat::from(0) {
+            return Err(PythiaError::TxWasNotSentToPma.into());
+        }
+
+        Balances::save_nonce(&chain_id, &address, &nat::from_u256(&tx.nonce))
+            .context(PythiaError::UnableToSaveNonce)?;
+        Balances::add_token_amount(
+            &chain_id,
+            &address,
+            &token.address,
+            &amount,
+            LedgerEntryKind::Deposit {
+                tx_hash: Some(tx_hash),
+            },
+        )
+        .context(PythiaError::UnableToIncreaseBalance)?;
+
+        log!("[{address}] deposited {amount} of {}", token.symbol);
+        return Ok(());
+    }
+
     if receiver != pma {
         return Err(PythiaError::TxWasNotSentToPma.into());
     }
@@ -82,8 +207,15 @@ async fn _deposit(chain_id: Nat, tx_hash: String, msg: String, sig: String) -> R
         return Ok(());
     }
 
-    Balances::add_amount(&chain_id, &address, &amount)
-        .context(PythiaError::UnableToIncreaseBalance)?;
+    Balances::add_amount(
+        &chain_id,
+        &address,
+        &amount,
+        LedgerEntryKind::Deposit {
+            tx_hash: Some(tx_hash),
+        },
+    )
+    .context(PythiaError::UnableToIncreaseBalance)?;
 
     log!("[{address}] deposited amount {amount}");
     Ok(())
@@ -94,46 +226,249 @@ async fn _deposit(chain_id: Nat, tx_hash: String, msg: String, sig: String) -> R
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
-/// * `receiver` - Address of the receiver, for example 0x1234567890abcdef1234567890abcdef12345678
 /// * `msg` - SIWE message, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
 /// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `receiver` - Address of the receiver, for example 0x1234567890abcdef1234567890abcdef12345678
+/// * `amount` - Amount to deduct from the balance, the transfer gas is paid from it. Can be omitted to withdraw the whole available balance
 ///
 /// # Returns
 ///
-/// Returns a result that can contain an error message
+/// Returns a result with the withdraw request id
 #[update]
 pub async fn withdraw(
     chain_id: Nat,
-    receiver: String,
     msg: String,
     sig: String,
-) -> Result<(), String> {
-    _withdraw(chain_id, receiver, msg, sig)
+    receiver: String,
+    amount: Option<Nat>,
+) -> Result<Nat, String> {
+    _withdraw(
+        chain_id,
+        Authorization::Siwe { msg, sig },
+        vec![(receiver, amount)],
+    )
+    .await
+    .map(|ids| ids[0].clone())
+    .map_err(|e| format!("failed to withdraw: {e:?}"))
+}
+
+/// Withdraw amount from the PMA for the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `receiver` - Address of the receiver, for example 0x1234567890abcdef1234567890abcdef12345678
+/// * `amount` - Amount to deduct from the balance, the transfer gas is paid from it. Can be omitted to withdraw the whole available balance
+///
+/// # Returns
+///
+/// Returns a result with the withdraw request id
+#[update]
+pub async fn session_withdraw(
+    chain_id: Nat,
+    receiver: String,
+    amount: Option<Nat>,
+) -> Result<Nat, String> {
+    _withdraw(chain_id, Authorization::Session, vec![(receiver, amount)])
         .await
+        .map(|ids| ids[0].clone())
         .map_err(|e| format!("failed to withdraw: {e:?}"))
 }
 
+/// Withdraw amounts from the PMA to multiple receivers with a single signed request
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `msg` - SIWE message, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `withdrawals` - Pairs of the receiver address and the amount to deduct from the balance, the transfer gas is paid from every amount
+///
+/// # Returns
+///
+/// Returns a result with the withdraw request ids in the same order as the withdrawals
+#[update]
+pub async fn withdraw_to_many(
+    chain_id: Nat,
+    msg: String,
+    sig: String,
+    withdrawals: Vec<(String, Nat)>,
+) -> Result<Vec<Nat>, String> {
+    _withdraw(
+        chain_id,
+        Authorization::Siwe { msg, sig },
+        withdrawals
+            .into_iter()
+            .map(|(receiver, amount)| (receiver, Some(amount)))
+            .collect(),
+    )
+    .await
+    .map_err(|e| format!("failed to withdraw: {e:?}"))
+}
+
 #[inline]
-async fn _withdraw(chain_id: Nat, msg: String, sig: String, receiver: String) -> Result<()> {
-    let address = siwe::siwe_recover(&msg, &sig)
-        .await
-        .context(PythiaError::UnableToRecoverAddress)?;
+async fn _withdraw(
+    chain_id: Nat,
+    auth: Authorization,
+    withdrawals: Vec<(String, Option<Nat>)>,
+) -> Result<Vec<Nat>> {
+    let address = auth.address(SiweAction::Withdraw, &chain_id).await?;
+    if withdrawals.is_empty() {
+        return Err(anyhow!("withdrawals are empty"));
+    }
+    let withdrawals = withdrawals
+        .into_iter()
+        .map(|(receiver, amount)| Ok((address::normalize(&receiver)?, amount)))
+        .collect::<Result<Vec<_>>>()
+        .context(PythiaError::InvalidAddressFormat)?;
+
     let gas_price = web3::gas_price(&chain_id)
         .await
         .context(PythiaError::UnableToGetGasPrice)?;
-    let amount = Balances::get_value_for_withdraw(&chain_id, &address, &gas_price)
+    let fee = canister::fee(&chain_id).await?;
+    // everything async is done before the balance is changed
+    let rates = Balances::token_rates(&chain_id, &address).await?;
+
+    let values = Balances::get_values_for_withdraw(&chain_id, &address, &gas_price, &withdrawals)
         .context(PythiaError::UnableToGetValueForWithdraw)?;
-    Subscriptions::stop_all(Some(chain_id.clone()), vec![], Some(address.clone()))
-        .context(PythiaError::UnableToStopSubscriptions)?;
-    WithdrawRequests::add(&chain_id, &receiver, &amount)
-        .context(PythiaError::UnableToAddWithdrawRequest)?;
+
+    // the amount is reduced by the share of the batch gas when the request is sent
+    let mut ids = vec![];
+    for ((receiver, _), value) in withdrawals.iter().zip(values) {
+        let id = WithdrawRequests::add(&chain_id, &address, receiver, &value, &value, None)
+            .context(PythiaError::UnableToAddWithdrawRequest)?;
+        log!("[{address}] withdrawed amount {value} to {receiver}");
+        ids.push(id);
+    }
+
+    let is_covering =
+        Balances::is_covering_subscriptions(&chain_id, &address, &gas_price, &fee, &rates)
+            .unwrap_or_else(|e| {
+                log!("[{address}] unable to check the subscriptions coverage: {e:?}");
+                false
+            });
+    if !is_covering {
+        let stopped =
+            Subscriptions::stop_all(Some(chain_id.clone()), vec![], Some(address.clone()))
+                .context(PythiaError::UnableToStopSubscriptions)?;
+        WithdrawRequests::set_stopped_subscriptions(&chain_id, &ids, &stopped)?;
+    }
 
     if !Timer::is_active() {
         withdraw::execute();
     }
 
-    log!("[{address}] withdrawed amount {amount}");
-    Ok(())
+    Ok(ids)
+}
+
+/// Withdraw ERC-20 tokens from the PMA, gas is paid in the withdrawn token
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `token` - Address of the token, for example 0x1234567890abcdef1234567890abcdef12345678
+/// * `msg` - SIWE message, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `receiver` - Address of the receiver, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the withdraw request id
+#[update]
+pub async fn withdraw_token(
+    chain_id: Nat,
+    token: String,
+    msg: String,
+    sig: String,
+    receiver: String,
+) -> Result<Nat, String> {
+    _withdraw_token(chain_id, token, Authorization::Siwe { msg, sig }, receiver)
+        .await
+        .map_err(|e| format!("failed to withdraw token: {e:?}"))
+}
+
+/// Withdraw ERC-20 tokens from the PMA for the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `token` - Address of the token, for example 0x1234567890abcdef1234567890abcdef12345678
+/// * `receiver` - Address of the receiver, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the withdraw request id
+#[update]
+pub async fn session_withdraw_token(
+    chain_id: Nat,
+    token: String,
+    receiver: String,
+) -> Result<Nat, String> {
+    _withdraw_token(chain_id, token, Authorization::Session, receiver)
+        .await
+        .map_err(|e| format!("failed to withdraw token: {e:?}"))
+}
+
+#[inline]
+async fn _withdraw_token(
+    chain_id: Nat,
+    token: String,
+    auth: Authorization,
+    receiver: String,
+) -> Result<Nat> {
+    let address = auth.address(SiweAction::WithdrawToken, &chain_id).await?;
+    let receiver = address::normalize(&receiver).context(PythiaError::InvalidAddressFormat)?;
+    let token = Chains::get_token(&chain_id, &token)?.context(PythiaError::TokenDoesNotExist)?;
+
+    let gas_price = web3::gas_price(&chain_id)
+        .await
+        .context(PythiaError::UnableToGetGasPrice)?;
+    let fee = canister::fee(&chain_id).await?;
+    let rates = erc20::rates(&chain_id).await?;
+    let rate = rates
+        .get(&token.address)
+        .context(PythiaError::UnableToGetTokenRate)?;
+    // the PMA sends the tokens with a direct transfer
+    let gas = rate.to_token(&(Nat::from(erc20::TOKEN_TRANSFER_GAS_LIMIT) * gas_price.clone()));
+
+    let amount = Balances::get_value_for_token_withdraw(
+        &chain_id,
+        &address,
+        &token.address,
+        &gas,
+        &receiver,
+        &rates,
+    )
+    .context(PythiaError::UnableToGetValueForWithdraw)?;
+    let id = WithdrawRequests::add(
+        &chain_id,
+        &address,
+        &receiver,
+        &amount,
+        &(amount.clone() + gas),
+        Some(token.address),
+    )
+    .context(PythiaError::UnableToAddWithdrawRequest)?;
+
+    let is_covering =
+        Balances::is_covering_subscriptions(&chain_id, &address, &gas_price, &fee, &rates)
+            .unwrap_or_else(|e| {
+                log!("[{address}] unable to check the subscriptions coverage: {e:?}");
+                false
+            });
+    if !is_covering {
+        let stopped =
+            Subscriptions::stop_all(Some(chain_id.clone()), vec![], Some(address.clone()))
+                .context(PythiaError::UnableToStopSubscriptions)?;
+        WithdrawRequests::set_stopped_subscriptions(&chain_id, &[id.clone()], &stopped)?;
+    }
+
+    if !Timer::is_active() {
+        withdraw::execute();
+    }
+
+    log!("[{address}] withdrawed {amount} of {}", token.symbol);
+    Ok(id)
 }
 
 /// Get balance of the user
@@ -155,3 +490,192 @@ fn _get_balance(chain_id: Nat, address: String) -> Result<Nat> {
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
     Ok(Balances::get(&chain_id, &address).unwrap_or_default())
 }
+
+/// Get ERC-20 token balances of the user
+///
+/// # Arguments
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with pairs of token address and balance
+#[query]
+pub fn get_token_balances(chain_id: Nat, address: String) -> Result<Vec<(String, Nat)>, String> {
+    _get_token_balances(chain_id, address)
+        .map_err(|e| format!("failed to get token balances: {e:?}"))
+}
+
+#[inline]
+fn _get_token_balances(chain_id: Nat, address: String) -> Result<Vec<(String, Nat)>> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    Ok(Balances::get_tokens(&chain_id, &address)
+        .unwrap_or_default()
+        .into_iter()
+        .collect())
+}
+
+/// Cancel the withdraw request that is not sent yet, the amount is restored to the balance
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `withdrawal_id` - The withdraw request id
+/// * `msg` - SIWE message, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `restart_subscriptions` - Restart the subscriptions stopped by the withdrawal once no request of it is pending, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn cancel_withdrawal(
+    chain_id: Nat,
+    withdrawal_id: Nat,
+    msg: String,
+    sig: String,
+    restart_subscriptions: Option<bool>,
+) -> Result<(), String> {
+    _cancel_withdrawal(
+        chain_id,
+        withdrawal_id,
+        Authorization::Siwe { msg, sig },
+        restart_subscriptions,
+    )
+    .await
+    .map_err(|e| format!("failed to cancel the withdrawal: {e:?}"))
+}
+
+/// Cancel the withdraw request of the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `withdrawal_id` - The withdraw request id
+/// * `restart_subscriptions` - Restart the subscriptions stopped by the withdrawal once no request of it is pending, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_cancel_withdrawal(
+    chain_id: Nat,
+    withdrawal_id: Nat,
+    restart_subscriptions: Option<bool>,
+) -> Result<(), String> {
+    _cancel_withdrawal(
+        chain_id,
+        withdrawal_id,
+        Authorization::Session,
+        restart_subscriptions,
+    )
+    .await
+    .map_err(|e| format!("failed to cancel the withdrawal: {e:?}"))
+}
+
+#[inline]
+async fn _cancel_withdrawal(
+    chain_id: Nat,
+    withdrawal_id: Nat,
+    auth: Authorization,
+    restart_subscriptions: Option<bool>,
+) -> Result<()> {
+    let address = auth
+        .address(SiweAction::CancelWithdrawal, &chain_id)
+        .await?;
+    let (req, stopped) = WithdrawRequests::cancel(&chain_id, &withdrawal_id, &address)?;
+
+    let kind = LedgerEntryKind::WithdrawalCancelled {
+        withdrawal_id: withdrawal_id.clone(),
+    };
+    match &req.token {
+        Some(token) => Balances::add_token_amount(&chain_id, &address, token, &req.charged, kind),
+        None => Balances::add_amount(&chain_id, &address, &req.charged, kind),
+    }
+    .context(PythiaError::UnableToIncreaseBalance)?;
+
+    // the subscriptions are restarted once no withdrawal of the batch is pending
+    if restart_subscriptions.unwrap_or_default() {
+        for sub_id in stopped {
+            if let Err(e) =
+                subscriptions::start_checked(&chain_id, &sub_id, &Actor::owner(&address)).await
+            {
+                log!("[{address}] unable to restart subscription {sub_id}: {e:?}");
+            }
+        }
+    }
+
+    log!("[{address}] cancelled withdrawal {withdrawal_id}");
+    Ok(())
+}
+
+/// Get the withdraw request
+///
+/// # Arguments
+/// * `id` - The withdraw request id
+///
+/// # Returns
+///
+/// Returns a result with the withdraw request and its status
+#[query]
+pub fn get_withdrawal(id: Nat) -> Result<WithdrawRequest, String> {
+    WithdrawRequests::get(&id).map_err(|e| format!("failed to get the withdrawal: {e:?}"))
+}
+
+/// Get the withdraw requests made by the address or sent to it
+///
+/// # Arguments
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the withdraw requests sorted by id
+#[query]
+pub fn get_withdrawals(address: String) -> Result<Vec<WithdrawRequest>, String> {
+    _get_withdrawals(address).map_err(|e| format!("failed to get withdrawals: {e:?}"))
+}
+
+#[inline]
+fn _get_withdrawals(address: String) -> Result<Vec<WithdrawRequest>> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    let mut withdrawals = WithdrawRequests::get_by_address(&address);
+    withdrawals.sort_by(|l, r| l.id.cmp(&r.id));
+
+    Ok(withdrawals)
+}
+
+/// Get the history of the user's balance changes, newest first. Only the latest entries are kept
+///
+/// # Arguments
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+/// * `pagination` - Pagination options, can be omitted
+///
+/// # Returns
+///
+/// Returns a result with ledger entries with or without pagination
+#[query]
+pub fn get_balance_history(
+    chain_id: Nat,
+    address: String,
+    pagination: Option<Pagination>,
+) -> Result<PaginationResult<LedgerEntry>, String> {
+    _get_balance_history(chain_id, address, pagination)
+        .map_err(|e| format!("failed to get balance history: {e:?}"))
+}
+
+#[inline]
+fn _get_balance_history(
+    chain_id: Nat,
+    address: String,
+    pagination: Option<Pagination>,
+) -> Result<PaginationResult<LedgerEntry>> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    let mut entries = Ledger::get(&chain_id, &address);
+    entries.reverse();
+
+    Ok(match pagination {
+        Some(pagination) => pagination.paginate(entries),
+        None => entries.into(),
+    })
+}
@@ -3,20 +3,28 @@ use candid::Nat;
 use ic_cdk::{query, update};
 
 use crate::{
+    jobs::withdraw,
     log,
     types::{
         balance::Balances,
-        chains::{ChainUpdator, Chains, CreateChainRequest},
+        chains::{
+            ChainFeeModel, ChainPauseReason, ChainToken, ChainUpdator, Chains, ChainsExport,
+            ChainsImportMode, CreateChainRequest, DecommissionStatus,
+        },
         logger::CHAINS,
+        roles::Role,
         subscription::Subscriptions,
-        withdraw::WithdrawRequests,
+        timer::Timer,
+        withdraw::{WithdrawRequests, WithdrawStatus},
     },
-    utils::{canister, validator},
+    utils::{canister, preflight, validator},
     Chain, PythiaError,
 };
 
 /// Add a new chain to the state.
 ///
+/// Requires the chain operator role.
+///
 /// # Arguments
 ///
 /// * `req` - the CreateChainRequest
@@ -33,25 +41,32 @@ pub async fn add_chain(req: CreateChainRequest) -> Result<(), String> {
 
 #[inline]
 async fn _add_chain(req: CreateChainRequest) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     if Chains::is_exists(&req.chain_id) {
         return Err(anyhow!(PythiaError::ChainAlreadyExists));
     }
+    preflight::check_chain(
+        &req.rpc,
+        &req.chain_id,
+        &req.block_gas_limit,
+        &req.multicall_contract,
+    )
+    .await?;
 
     Chains::add(&req).context(PythiaError::UnableToAddNewChain)?;
 
-    Balances::init_new_chain(&req.chain_id).context(PythiaError::UnableToAddNewChain)?;
-    Subscriptions::init_new_chain(&req.chain_id).context(PythiaError::UnableToAddNewChain)?;
-    WithdrawRequests::init_new_chain(&req.chain_id).context(PythiaError::UnableToAddNewChain)?;
-
     let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
-    Balances::create(&req.chain_id, &pma).context(PythiaError::UnableToAddNewBalance)?;
+    init_new_chain(&req.chain_id, &pma)?;
 
     log!("[{CHAINS}] added, id: {}", req.chain_id);
     Ok(())
 }
 
-/// Remove a chain from the state.
+/// Start the chain removal. The chain is paused, its subscriptions are stopped and every user's balance
+/// is withdrawn to the user's address, the chain is removed once all the withdrawals are confirmed.
+/// Calling it again for the decommissioning chain retries the withdrawals.
+///
+/// Requires the chain operator role.
 ///
 /// # Arguments
 ///
@@ -61,24 +76,124 @@ async fn _add_chain(req: CreateChainRequest) -> Result<()> {
 ///
 /// Returns a result that can contain an error message
 #[update]
-pub fn remove_chain(chain_id: Nat) -> Result<(), String> {
-    _remove_chain(chain_id).map_err(|e| format!("failed to remove a chain: {e:?}"))
+pub async fn remove_chain(chain_id: Nat) -> Result<(), String> {
+    _remove_chain(chain_id)
+        .await
+        .map_err(|e| format!("failed to remove a chain: {e:?}"))
+}
+
+#[inline]
+async fn _remove_chain(chain_id: Nat) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::start_decommission(&chain_id).context(PythiaError::UnableToRemoveChain)?;
+    Subscriptions::stop_all(Some(chain_id.clone()), vec![], None)
+        .context(PythiaError::UnableToStopSubscriptions)?;
+
+    if !Timer::is_active() {
+        withdraw::execute();
+    }
+
+    log!("[{CHAINS}] decommission started, id: {chain_id}");
+    Ok(())
+}
+
+/// Get the progress of the chain removal.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+///
+/// # Returns
+///
+/// Returns a result with the decommission status, the chain doesn't exist after it's removed
+#[query]
+pub fn get_chain_decommission_status(chain_id: Nat) -> Result<DecommissionStatus, String> {
+    _get_chain_decommission_status(chain_id)
+        .map_err(|e| format!("failed to get a chain decommission status: {e:?}"))
 }
 
 #[inline]
-fn _remove_chain(chain_id: Nat) -> Result<()> {
-    validator::caller()?;
-    Chains::remove(&chain_id).context(PythiaError::UnableToRemoveChain)?;
+fn _get_chain_decommission_status(chain_id: Nat) -> Result<DecommissionStatus> {
+    let decommission = Chains::get(&chain_id)?
+        .decommission
+        .context(PythiaError::ChainIsNotDecommissioning)?;
+
+    let mut status = DecommissionStatus {
+        started_at: decommission.started_at,
+        withdrawals_total: Nat::from(decommission.withdrawal_ids.len()),
+        dust_balances: decommission.dust_balances,
+        ..Default::default()
+    };
+    for req in WithdrawRequests::get_by_chain(&chain_id) {
+        if !decommission.withdrawal_ids.contains(&req.id) {
+            continue;
+        }
+
+        match req.status {
+            WithdrawStatus::Confirmed { .. } => status.withdrawals_confirmed += 1,
+            WithdrawStatus::Failed { .. } | WithdrawStatus::Abandoned { .. } => {
+                status.withdrawals_failed += 1
+            }
+            _ => status.withdrawals_pending += 1,
+        }
+    }
+
+    Ok(status)
+}
+
+/// Suspend publishing for a chain, subscriptions keep their own state and are published again after resume.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn pause_chain(chain_id: Nat) -> Result<(), String> {
+    _pause_chain(chain_id).map_err(|e| format!("failed to pause a chain: {e:?}"))
+}
+
+#[inline]
+fn _pause_chain(chain_id: Nat) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::pause(&chain_id, ChainPauseReason::Manual).context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] paused, id: {chain_id}");
+    Ok(())
+}
 
-    Balances::remove_chain(&chain_id).context(PythiaError::UnableToRemoveChain)?;
-    Subscriptions::deinit_chain(&chain_id).context(PythiaError::UnableToRemoveChain)?;
-    WithdrawRequests::deinit_chain(&chain_id).context(PythiaError::UnableToRemoveChain)?;
+/// Resume publishing for a chain paused by a controller or due to errors.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn resume_chain(chain_id: Nat) -> Result<(), String> {
+    _resume_chain(chain_id).map_err(|e| format!("failed to resume a chain: {e:?}"))
+}
 
-    log!("[{CHAINS}] removed, id: {chain_id}");
+#[inline]
+fn _resume_chain(chain_id: Nat) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::resume(&chain_id).context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] resumed, id: {chain_id}");
     Ok(())
 }
 
-/// Update a chain RPC in the state.
+/// Update a chain RPC in the state, the chain config is checked against the new RPC.
+///
+/// Requires the chain operator role.
 ///
 /// # Arguments
 ///
@@ -89,13 +204,26 @@ fn _remove_chain(chain_id: Nat) -> Result<()> {
 ///
 /// Returns a result that can contain an error message
 #[update]
-pub fn update_chain_rpc(chain_id: Nat, rpc: String) -> Result<(), String> {
-    _update_chain_rpc(chain_id, rpc).map_err(|e| format!("failed to update a chain RPC: {e:?}"))
+pub async fn update_chain_rpc(chain_id: Nat, rpc: String) -> Result<(), String> {
+    _update_chain_rpc(chain_id, rpc)
+        .await
+        .map_err(|e| format!("failed to update a chain RPC: {e:?}"))
 }
 
 #[inline]
-fn _update_chain_rpc(chain_id: Nat, rpc: String) -> Result<()> {
-    validator::caller()?;
+async fn _update_chain_rpc(chain_id: Nat, rpc: String) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    let chain = Chains::get(&chain_id)?;
+    preflight::check_chain(
+        &rpc,
+        &chain_id,
+        &chain.block_gas_limit,
+        &chain
+            .multicall_contract
+            .context("multicall contract should be set")?,
+    )
+    .await?;
+
     Chains::update(
         &chain_id,
         ChainUpdator {
@@ -109,18 +237,40 @@ fn _update_chain_rpc(chain_id: Nat, rpc: String) -> Result<()> {
     Ok(())
 }
 
+/// Update a chain multicall contract, the contract is checked on the chain before it's saved.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `multicall_contract` - Address of the multicall contract.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
 #[update]
-pub fn update_chain_multicall_contract(
+pub async fn update_chain_multicall_contract(
     chain_id: Nat,
     multicall_contract: String,
 ) -> Result<(), String> {
     _update_chain_multicall_contract(chain_id, multicall_contract)
+        .await
         .map_err(|e| format!("failed to update a chain multicallcontract: {e:?}"))
 }
 
 #[inline]
-fn _update_chain_multicall_contract(chain_id: Nat, multicall_contract: String) -> Result<()> {
-    validator::caller()?;
+async fn _update_chain_multicall_contract(chain_id: Nat, multicall_contract: String) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    let chain = Chains::get(&chain_id)?;
+    preflight::check_chain(
+        &chain.rpc,
+        &chain_id,
+        &chain.block_gas_limit,
+        &multicall_contract,
+    )
+    .await?;
+
     Chains::update(
         &chain_id,
         ChainUpdator {
@@ -134,8 +284,64 @@ fn _update_chain_multicall_contract(chain_id: Nat, multicall_contract: String) -
     Ok(())
 }
 
+/// Add an ERC-20 token accepted for deposits on a chain.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `token` - Token config, `feed_id` is a Sybil feed used to convert gas costs to the token.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn add_chain_token(chain_id: Nat, token: ChainToken) -> Result<(), String> {
+    _add_chain_token(chain_id, token).map_err(|e| format!("failed to add a chain token: {e:?}"))
+}
+
+#[inline]
+fn _add_chain_token(chain_id: Nat, token: ChainToken) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    let symbol = token.symbol.clone();
+    Chains::add_token(&chain_id, token).context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] token added: {symbol}, id: {chain_id}");
+    Ok(())
+}
+
+/// Remove an ERC-20 token from a chain.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `token` - Address of the token.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn remove_chain_token(chain_id: Nat, token: String) -> Result<(), String> {
+    _remove_chain_token(chain_id, token)
+        .map_err(|e| format!("failed to remove a chain token: {e:?}"))
+}
+
+#[inline]
+fn _remove_chain_token(chain_id: Nat, token: String) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::remove_token(&chain_id, &token).context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] token removed: {token}, id: {chain_id}");
+    Ok(())
+}
+
 /// Update a chain minimum balance in the state.
 ///
+/// Requires the chain operator role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -152,7 +358,7 @@ pub fn update_chain_min_balance(chain_id: Nat, min_balance: Nat) -> Result<(), S
 
 #[inline]
 fn _update_chain_min_balance(chain_id: Nat, min_balance: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     Chains::update(
         &chain_id,
         ChainUpdator {
@@ -168,6 +374,8 @@ fn _update_chain_min_balance(chain_id: Nat, min_balance: Nat) -> Result<()> {
 
 /// Update a chain fee and symbol.
 ///
+/// Requires the chain operator role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -185,7 +393,7 @@ pub fn update_chain_fee_and_symbol(chain_id: Nat, fee: Nat, symbol: String) -> R
 
 #[inline]
 fn _update_chain_fee_and_symbol(chain_id: Nat, fee: Nat, symbol: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     Chains::update(
         &chain_id,
         ChainUpdator {
@@ -202,6 +410,8 @@ fn _update_chain_fee_and_symbol(chain_id: Nat, fee: Nat, symbol: String) -> Resu
 
 /// Update a chain block gas limit
 ///
+/// Requires the chain operator role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -218,7 +428,7 @@ pub fn update_chain_block_gas_limit(chain_id: Nat, block_gas_limit: Nat) -> Resu
 
 #[inline]
 fn _update_chain_block_gas_limit(chain_id: Nat, block_gas_limit: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     Chains::update(
         &chain_id,
         ChainUpdator {
@@ -232,8 +442,141 @@ fn _update_chain_block_gas_limit(chain_id: Nat, block_gas_limit: Nat) -> Result<
     Ok(())
 }
 
+/// Update a chain max gas price, subscriptions on the chain are deferred while the gas price is above it
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `max_gas_price` - Max gas price in wei, `None` removes the ceiling.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn update_chain_max_gas_price(chain_id: Nat, max_gas_price: Option<Nat>) -> Result<(), String> {
+    _update_chain_max_gas_price(chain_id, max_gas_price)
+        .map_err(|e| format!("failed to update a chain max gas price: {e:?}"))
+}
+
+#[inline]
+fn _update_chain_max_gas_price(chain_id: Nat, max_gas_price: Option<Nat>) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::set_max_gas_price(&chain_id, max_gas_price.clone())
+        .context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] max gas price updated: {max_gas_price:?}, id: {chain_id}");
+    Ok(())
+}
+
+/// Update a chain fee model, rollups charge the L1 data fee on top of the L2 gas
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `fee_model` - Plain, OpStack or Arbitrum.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn update_chain_fee_model(chain_id: Nat, fee_model: ChainFeeModel) -> Result<(), String> {
+    _update_chain_fee_model(chain_id, fee_model)
+        .map_err(|e| format!("failed to update a chain fee model: {e:?}"))
+}
+
+#[inline]
+fn _update_chain_fee_model(chain_id: Nat, fee_model: ChainFeeModel) -> Result<()> {
+    validator::role(Role::ChainOperator)?;
+    Chains::set_fee_model(&chain_id, fee_model.clone())
+        .context(PythiaError::UnableToUpdateChain)?;
+
+    log!("[{CHAINS}] fee model updated: {fee_model:?}, id: {chain_id}");
+    Ok(())
+}
+
+/// Export the configuration of all the chains, runtime state like error counters is excluded.
+///
+/// Requires the support role.
+///
+/// # Returns
+///
+/// Returns a result with the versioned document accepted by `import_chains`
+#[query]
+pub fn export_chains() -> Result<ChainsExport, String> {
+    _export_chains().map_err(|e| format!("failed to export chains: {e:?}"))
+}
+
+#[inline]
+fn _export_chains() -> Result<ChainsExport> {
+    validator::role(Role::Support)?;
+    Ok(Chains::export())
+}
+
+/// Import chains from the document produced by `export_chains`. The document is validated as a whole
+/// and every chain passes the same preflight checks as in `add_chain` before anything is changed,
+/// new chains are initialized like in `add_chain`.
+///
+/// Requires the chain operator role.
+///
+/// # Arguments
+///
+/// * `doc` - Versioned chains configuration.
+/// * `mode` - Upsert, CreateOnly or UpdateOnly.
+///
+/// # Returns
+///
+/// Returns a result with the ids of the added chains
+#[update]
+pub async fn import_chains(doc: ChainsExport, mode: ChainsImportMode) -> Result<Vec<Nat>, String> {
+    _import_chains(doc, mode)
+        .await
+        .map_err(|e| format!("failed to import chains: {e:?}"))
+}
+
+#[inline]
+async fn _import_chains(doc: ChainsExport, mode: ChainsImportMode) -> Result<Vec<Nat>> {
+    validator::role(Role::ChainOperator)?;
+    for config in Chains::validate_import(doc.clone(), &mode)? {
+        preflight::check_chain(
+            &config.rpc,
+            &config.chain_id,
+            &config.block_gas_limit,
+            &config.multicall_contract,
+        )
+        .await
+        .with_context(|| format!("preflight checks failed, chain_id = {}", config.chain_id))?;
+    }
+    let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
+
+    // the state could change while awaiting, so the document is validated again,
+    // nothing is awaited below, trapping rolls back the whole import
+    let configs = Chains::validate_import(doc, &mode)?;
+    let new_chain_ids = Chains::import(configs);
+    for chain_id in &new_chain_ids {
+        if let Err(e) = init_new_chain(chain_id, &pma) {
+            ic_cdk::trap(&format!("failed to initialize the chain {chain_id}: {e:?}"));
+        }
+    }
+
+    log!("[{CHAINS}] imported, new ids: {new_chain_ids:?}");
+    Ok(new_chain_ids)
+}
+
+fn init_new_chain(chain_id: &Nat, pma: &str) -> Result<()> {
+    Balances::init_new_chain(chain_id).context(PythiaError::UnableToAddNewChain)?;
+    Subscriptions::init_new_chain(chain_id).context(PythiaError::UnableToAddNewChain)?;
+    WithdrawRequests::init_new_chain(chain_id).context(PythiaError::UnableToAddNewChain)?;
+    Balances::create(chain_id, pma).context(PythiaError::UnableToAddNewBalance)
+}
+
 /// Get a chain RPC from the state.
 ///
+/// Requires the support role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -248,7 +591,7 @@ pub fn get_chain_rpc(chain_id: Nat) -> Result<String, String> {
 
 #[inline]
 fn _get_chain_rpc(chain_id: Nat) -> Result<String> {
-    validator::caller()?;
+    validator::role(Role::Support)?;
     Chains::get_rpc(&chain_id).context(PythiaError::UnableToGetChainRPC)
 }
 
@@ -5,7 +5,10 @@ use ic_cdk::{query, update};
 use crate::{
     jobs::{publisher, withdraw},
     log,
-    types::{balance::Balances, logger::CONTROLLERS, state::State, timer::Timer},
+    types::{
+        balance::Balances, ledger::LedgerEntryKind, logger::CONTROLLERS, roles::Role,
+        siwe::SiweConfig, state::State, timer::Timer,
+    },
     update_state,
     utils::{address, canister, validator, web3},
     PythiaError, STATE,
@@ -13,6 +16,8 @@ use crate::{
 
 /// Update the tx fee.
 ///
+/// Requires the owner role.
+///
 /// # Arguments
 ///
 /// * `tx_fee` - New tx fee, used for collecting fee from balances.
@@ -27,7 +32,7 @@ pub fn update_tx_fee(tx_fee: Nat) -> Result<(), String> {
 
 #[inline]
 pub fn _update_tx_fee(tx_fee: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Owner)?;
     update_state!(tx_fee, tx_fee.clone());
     log!("[{CONTROLLERS}] tx fee updated: {tx_fee}");
     Ok(())
@@ -35,21 +40,26 @@ pub fn _update_tx_fee(tx_fee: Nat) -> Result<()> {
 
 /// Get the current state.
 ///
+/// Requires the support role.
+///
 /// # Returns
 ///
-/// Returns the current state
+/// Returns a result with the current state
 #[query]
-pub fn get_cfg() -> State {
-    _get_cfg()
+pub fn get_cfg() -> Result<State, String> {
+    _get_cfg().map_err(|e| format!("failed to get the state: {e:?}"))
 }
 
 #[inline]
-pub fn _get_cfg() -> State {
-    STATE.with(|state| state.borrow().clone())
+pub fn _get_cfg() -> Result<State> {
+    validator::role(Role::Support)?;
+    Ok(STATE.with(|state| state.borrow().clone()))
 }
 
 /// Update the subscriptions limit for a wallet.
 ///
+/// Requires the owner role.
+///
 /// # Arguments
 ///
 /// * `limit` - New subs limit for a wallet, used to check if there is subscriptions for a waller overflow.
@@ -65,7 +75,7 @@ pub fn update_subs_limit_wallet(limit: Nat) -> Result<(), String> {
 
 #[inline]
 fn _update_subs_limit_wallet(limit: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Owner)?;
     update_state!(subs_limit_wallet, limit.clone());
     log!("[{CONTROLLERS}] subscriptions limit for a wallet updated: {limit}");
     Ok(())
@@ -73,6 +83,8 @@ fn _update_subs_limit_wallet(limit: Nat) -> Result<()> {
 
 /// Update the subscriptions limit total.
 ///
+/// Requires the owner role.
+///
 /// # Arguments
 ///
 /// * `limit` - New subs limit total, used to check if there is subscriptions overflow.
@@ -88,7 +100,7 @@ pub fn update_subs_limit_total(limit: Nat) -> Result<(), String> {
 
 #[inline]
 fn _update_subs_limit_total(limit: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Owner)?;
     update_state!(subs_limit_total, limit.clone());
     log!("[{CONTROLLERS}] subscriptions limit: {limit}");
     Ok(())
@@ -96,6 +108,8 @@ fn _update_subs_limit_total(limit: Nat) -> Result<()> {
 
 /// Update the timer frequency.
 ///
+/// Requires the owner role.
+///
 /// # Arguments
 ///
 /// * `frequency` - New timer frequency, when will a new timer will be executed.
@@ -111,14 +125,89 @@ pub fn update_timer_frequency(frequency: Nat) -> Result<(), String> {
 
 #[inline]
 fn _update_timer_frequency(frequency: Nat) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Owner)?;
     update_state!(timer_frequency, frequency.clone());
     log!("[{CONTROLLERS}] the timer frequency updated: {frequency}");
     Ok(())
 }
 
+/// Update the runway warning threshold.
+///
+/// Requires the owner role.
+///
+/// # Arguments
+///
+/// * `threshold` - Seconds of runway below which subscriptions are marked as at risk.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn update_runway_warning_threshold(threshold: Nat) -> Result<(), String> {
+    _update_runway_warning_threshold(threshold)
+        .map_err(|e| format!("failed to update the runway warning threshold: {e:?}"))
+}
+
+#[inline]
+fn _update_runway_warning_threshold(threshold: Nat) -> Result<()> {
+    validator::role(Role::Owner)?;
+    update_state!(runway_warning_threshold, threshold.clone());
+    log!("[{CONTROLLERS}] the runway warning threshold updated: {threshold}");
+    Ok(())
+}
+
+/// Update the domains SIWE messages are allowed to be issued for.
+///
+/// Requires the owner role.
+///
+/// # Arguments
+///
+/// * `domains` - Allowed domains, an empty list disables the check.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn update_siwe_allowed_domains(domains: Vec<String>) -> Result<(), String> {
+    _update_siwe_allowed_domains(domains)
+        .map_err(|e| format!("failed to update the SIWE allowed domains: {e:?}"))
+}
+
+#[inline]
+fn _update_siwe_allowed_domains(domains: Vec<String>) -> Result<()> {
+    validator::role(Role::Owner)?;
+    SiweConfig::set_allowed_domains(domains);
+    Ok(())
+}
+
+/// Update the URIs SIWE messages are allowed to be issued for.
+///
+/// Requires the owner role.
+///
+/// # Arguments
+///
+/// * `uris` - Allowed URIs, an empty list disables the check.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn update_siwe_allowed_uris(uris: Vec<String>) -> Result<(), String> {
+    _update_siwe_allowed_uris(uris)
+        .map_err(|e| format!("failed to update the SIWE allowed uris: {e:?}"))
+}
+
+#[inline]
+fn _update_siwe_allowed_uris(uris: Vec<String>) -> Result<()> {
+    validator::role(Role::Owner)?;
+    SiweConfig::set_allowed_uris(uris);
+    Ok(())
+}
+
 /// Execute the withdraw job
 ///
+/// Requires the chain operator role.
+///
 /// # Returns
 ///
 /// Returns a result that can contain an error message
@@ -129,7 +218,7 @@ pub fn execute_withdraw_job() -> Result<(), String> {
 
 #[inline]
 fn _execute_withdraw_job() -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     withdraw::execute();
     log!("[{CONTROLLERS}] withdraw job forcefully executed");
     Ok(())
@@ -137,6 +226,8 @@ fn _execute_withdraw_job() -> Result<()> {
 
 /// Execute the publisher job
 ///
+/// Requires the chain operator role.
+///
 /// # Returns
 ///
 /// Returns a result that can contain an error message
@@ -147,7 +238,7 @@ pub fn execute_publisher_job() -> Result<(), String> {
 
 #[inline]
 fn _execute_publisher_job() -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     publisher::execute();
     log!("[{CONTROLLERS}] publisher job forcefully executed");
     Ok(())
@@ -155,6 +246,8 @@ fn _execute_publisher_job() -> Result<()> {
 
 /// Withdraw the platform fees.
 ///
+/// Requires the treasury role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -172,14 +265,22 @@ pub async fn withdraw_fee(chain_id: Nat, receiver: String) -> Result<(), String>
 
 #[inline]
 async fn _withdraw_fee(chain_id: Nat, receiver: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Treasury)?;
     let receiver = address::normalize(&receiver).context(PythiaError::InvalidAddressFormat)?;
     let pma = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
     let value = Balances::get(&chain_id, &pma).context(PythiaError::UnableToGetBalance)?;
     web3::transfer(&chain_id, &receiver, &value)
         .await
         .context(PythiaError::UnableToTransferFunds)?;
-    Balances::reduce(&chain_id, &pma, &value).context(PythiaError::UnableToReduceBalance)?;
+    Balances::reduce(
+        &chain_id,
+        &pma,
+        &value,
+        LedgerEntryKind::Withdrawal {
+            receiver: receiver.clone(),
+        },
+    )
+    .context(PythiaError::UnableToReduceBalance)?;
 
     log!("[] fees were withdrawn to: {receiver}");
     Ok(())
@@ -187,6 +288,8 @@ async fn _withdraw_fee(chain_id: Nat, receiver: String) -> Result<()> {
 
 /// Withdraw all the balance.
 ///
+/// Requires the treasury role.
+///
 /// # Arguments
 ///
 /// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
@@ -204,7 +307,7 @@ pub async fn withdraw_all_balance(chain_id: Nat, receiver: String) -> Result<(),
 
 #[inline]
 async fn _withdraw_all_balance(chain_id: Nat, receiver: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Treasury)?;
     let receiver = address::normalize(&receiver).context(PythiaError::InvalidAddressFormat)?;
 
     web3::transfer_all(&chain_id, &receiver)
@@ -217,6 +320,8 @@ async fn _withdraw_all_balance(chain_id: Nat, receiver: String) -> Result<()> {
 
 /// Stop main timer
 ///
+/// Requires the chain operator role.
+///
 /// # Returns
 ///
 /// Returns a result that can contain an error message
@@ -227,12 +332,24 @@ pub fn stop_timer() -> Result<(), String> {
 
 #[inline]
 fn _stop_timer() -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::ChainOperator)?;
     Timer::deactivate().context(PythiaError::UnableToDeactivateTimer)?;
     log!("[{CONTROLLERS}] timer was stopped");
     Ok(())
 }
 
+/// Clear the balance of the user.
+///
+/// Requires the treasury role.
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
 #[update]
 pub fn clear_balance(chain_id: Nat, address: String) -> Result<(), String> {
     _clear_balance(chain_id, address).map_err(|e| format!("failed to clear the balance: {e:?}"))
@@ -240,7 +357,7 @@ pub fn clear_balance(chain_id: Nat, address: String) -> Result<(), String> {
 
 #[inline]
 fn _clear_balance(chain_id: Nat, address: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::Treasury)?;
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
     Balances::clear(&chain_id, &address).context(PythiaError::UnableToClearBalance)?;
     log!("[{CONTROLLERS}] balance was cleared for: {address}");
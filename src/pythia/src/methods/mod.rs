@@ -1,7 +1,12 @@
+pub mod access_requests;
 pub mod balance;
 pub mod chains;
 pub mod controllers;
 pub mod http;
+pub mod operators;
+pub mod roles;
+pub mod service_accounts;
+pub mod sessions;
 pub mod signatures;
 pub mod subscriptions;
 pub mod whitelist;
@@ -0,0 +1,140 @@
+use anyhow::{Context, Result};
+use candid::{Nat, Principal};
+use ic_cdk::{query, update};
+
+use crate::{
+    types::{
+        errors::PythiaError,
+        operators::{OperatorGrant, OperatorGrantee, OperatorScope, Operators},
+        siwe::SiweAction,
+        subscription::Subscriptions,
+    },
+    utils::{address, siwe, time},
+};
+
+/// Grant operator rights over the signer's subscriptions. Operators can stop, start and update
+/// the subscriptions in the scope, withdrawals stay owner-only.
+///
+/// # Arguments
+///
+/// * `msg` - SIWE message, it must list `urn:pythia:manage_operators` in its resources
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `grantee` - EVM address or IC principal of the operator
+/// * `scope` - Global, a chain or a single subscription
+/// * `expires_at` - Timestamp in seconds the grant expires at, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn grant_operator(
+    msg: String,
+    sig: String,
+    grantee: OperatorGrantee,
+    scope: OperatorScope,
+    expires_at: Option<Nat>,
+) -> Result<(), String> {
+    _grant_operator(msg, sig, grantee, scope, expires_at)
+        .await
+        .map_err(|e| format!("failed to grant an operator: {e:?}"))
+}
+
+#[inline]
+async fn _grant_operator(
+    msg: String,
+    sig: String,
+    grantee: OperatorGrantee,
+    scope: OperatorScope,
+    expires_at: Option<Nat>,
+) -> Result<()> {
+    let owner = siwe::siwe_authorize_unbound(&msg, &sig, SiweAction::ManageOperators)
+        .await
+        .map_err(PythiaError::from)?;
+    let grantee = normalize_grantee(grantee)?;
+
+    if let OperatorScope::Subscription { chain_id, id } = &scope {
+        if Subscriptions::get(chain_id, id)?.owner != owner {
+            return Err(PythiaError::SubscriptionDoesNotExist.into());
+        }
+    }
+    if let Some(expires_at) = &expires_at {
+        if *expires_at <= Nat::from(time::in_seconds()) {
+            return Err(PythiaError::InvalidOperatorGrantExpiration.into());
+        }
+    }
+
+    Operators::grant(&owner, grantee, scope, expires_at);
+    Ok(())
+}
+
+/// Revoke operator rights granted by the signer
+///
+/// # Arguments
+///
+/// * `msg` - SIWE message, it must list `urn:pythia:manage_operators` in its resources
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `grantee` - EVM address or IC principal of the operator
+/// * `scope` - Scope of the grant to revoke
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn revoke_operator(
+    msg: String,
+    sig: String,
+    grantee: OperatorGrantee,
+    scope: OperatorScope,
+) -> Result<(), String> {
+    _revoke_operator(msg, sig, grantee, scope)
+        .await
+        .map_err(|e| format!("failed to revoke an operator: {e:?}"))
+}
+
+#[inline]
+async fn _revoke_operator(
+    msg: String,
+    sig: String,
+    grantee: OperatorGrantee,
+    scope: OperatorScope,
+) -> Result<()> {
+    let owner = siwe::siwe_authorize_unbound(&msg, &sig, SiweAction::ManageOperators)
+        .await
+        .map_err(PythiaError::from)?;
+    let grantee = normalize_grantee(grantee)?;
+
+    Operators::revoke(&owner, &grantee, &scope)
+}
+
+/// Get the active operator grants of the owner
+///
+/// # Arguments
+///
+/// * `owner` - Address of the owner, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the operator grants
+#[query]
+pub fn get_operators(owner: String) -> Result<Vec<OperatorGrant>, String> {
+    _get_operators(owner).map_err(|e| format!("failed to get operators: {e:?}"))
+}
+
+#[inline]
+fn _get_operators(owner: String) -> Result<Vec<OperatorGrant>> {
+    let owner = address::normalize(&owner).context(PythiaError::InvalidAddressFormat)?;
+
+    Ok(Operators::get(&owner))
+}
+
+fn normalize_grantee(grantee: OperatorGrantee) -> Result<OperatorGrantee> {
+    match grantee {
+        OperatorGrantee::Address(address) => Ok(OperatorGrantee::Address(
+            address::normalize(&address).context(PythiaError::InvalidAddressFormat)?,
+        )),
+        OperatorGrantee::Principal(principal) if principal == Principal::anonymous() => {
+            Err(PythiaError::AnonymousPrincipal.into())
+        }
+        grantee => Ok(grantee),
+    }
+}
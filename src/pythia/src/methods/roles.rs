@@ -0,0 +1,80 @@
+use anyhow::Result;
+use candid::Principal;
+use ic_cdk::{query, update};
+
+use crate::{
+    types::{
+        errors::PythiaError,
+        roles::{Role, Roles},
+    },
+    utils::validator,
+};
+
+/// Grant a role to a principal, only controllers can manage roles
+///
+/// # Arguments
+///
+/// * `principal` - Principal to grant the role to
+/// * `role` - Role to grant
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    _grant_role(principal, role).map_err(|e| format!("failed to grant the role: {e:?}"))
+}
+
+#[inline]
+fn _grant_role(principal: Principal, role: Role) -> Result<()> {
+    validator::caller()?;
+
+    if principal == Principal::anonymous() {
+        return Err(PythiaError::AnonymousPrincipal.into());
+    }
+    Roles::grant(principal, role);
+
+    Ok(())
+}
+
+/// Revoke a role from a principal, only controllers can manage roles
+///
+/// # Arguments
+///
+/// * `principal` - Principal to revoke the role from
+/// * `role` - Role to revoke
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn revoke_role(principal: Principal, role: Role) -> Result<(), String> {
+    _revoke_role(principal, role).map_err(|e| format!("failed to revoke the role: {e:?}"))
+}
+
+#[inline]
+fn _revoke_role(principal: Principal, role: Role) -> Result<()> {
+    validator::caller()?;
+    Roles::revoke(&principal, role);
+
+    Ok(())
+}
+
+/// Get the granted roles
+///
+/// Requires the support role.
+///
+/// # Returns
+///
+/// Returns a result with pairs of principal and its roles
+#[query]
+pub fn get_roles() -> Result<Vec<(Principal, Vec<Role>)>, String> {
+    _get_roles().map_err(|e| format!("failed to get roles: {e:?}"))
+}
+
+#[inline]
+fn _get_roles() -> Result<Vec<(Principal, Vec<Role>)>> {
+    validator::role(Role::Support)?;
+
+    Ok(Roles::get_all())
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use candid::Principal;
+use ic_cdk::{query, update};
+
+use crate::{
+    types::{
+        errors::PythiaError,
+        service_accounts::{ServiceAccount, ServiceAccounts},
+    },
+    utils::{address, validator},
+};
+
+/// Add a service account that acts for the owner in the subscription endpoints
+///
+/// # Arguments
+///
+/// * `principal` - Principal of the service account
+/// * `owner` - EVM address the service account acts for, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn add_service_account(principal: Principal, owner: String) -> Result<(), String> {
+    _add_service_account(principal, owner)
+        .map_err(|e| format!("failed to add a service account: {e:?}"))
+}
+
+#[inline]
+fn _add_service_account(principal: Principal, owner: String) -> Result<()> {
+    validator::caller()?;
+
+    if principal == Principal::anonymous() {
+        return Err(PythiaError::AnonymousPrincipal.into());
+    }
+    let owner = address::normalize(&owner).context(PythiaError::InvalidAddressFormat)?;
+
+    ServiceAccounts::add(principal, &owner, ic_cdk::caller())
+}
+
+/// Remove a service account
+///
+/// # Arguments
+///
+/// * `principal` - Principal of the service account
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn remove_service_account(principal: Principal) -> Result<(), String> {
+    _remove_service_account(principal)
+        .map_err(|e| format!("failed to remove a service account: {e:?}"))
+}
+
+#[inline]
+fn _remove_service_account(principal: Principal) -> Result<()> {
+    validator::caller()?;
+
+    ServiceAccounts::remove(&principal, ic_cdk::caller())
+}
+
+/// Get all service accounts
+///
+/// # Returns
+///
+/// Returns a result with the service accounts
+#[query]
+pub fn get_service_accounts() -> Result<Vec<ServiceAccount>, String> {
+    _get_service_accounts().map_err(|e| format!("failed to get service accounts: {e:?}"))
+}
+
+#[inline]
+fn _get_service_accounts() -> Result<Vec<ServiceAccount>> {
+    validator::caller()?;
+
+    Ok(ServiceAccounts::get_all())
+}
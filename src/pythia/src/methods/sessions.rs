@@ -0,0 +1,99 @@
+use anyhow::Result;
+use candid::{Nat, Principal};
+use ic_cdk::{query, update};
+
+use crate::{
+    types::{
+        session::{Session, Sessions, SESSION_MAX_DURATION},
+        whitelist,
+    },
+    utils::{nat, siwe},
+    PythiaError,
+};
+
+/// Bind the SIWE signer address to the caller's principal for a limited time.
+///
+/// # Arguments
+///
+/// * `msg` - SIWE message, it must list `urn:pythia:login` and the granted `urn:pythia:session:*` permissions in its resources
+/// * `sig` - SIWE signature, For more information, refer to the [SIWE message specification](https://eips.ethereum.org/EIPS/eip-4361)
+/// * `duration` - Session lifetime in seconds, at most a week
+///
+/// # Returns
+///
+/// Returns a result with the created session
+#[update]
+pub async fn login(msg: String, sig: String, duration: Nat) -> Result<Session, String> {
+    _login(msg, sig, duration)
+        .await
+        .map_err(|e| format!("failed to login: {e:?}"))
+}
+
+#[inline]
+async fn _login(msg: String, sig: String, duration: Nat) -> Result<Session> {
+    let (address, permissions) = siwe::siwe_login(&msg, &sig)
+        .await
+        .map_err(PythiaError::from)?;
+    if !whitelist::is_whitelisted(&address) {
+        return Err(PythiaError::UserIsNotWhitelisted.into());
+    }
+    if duration > Nat::from(SESSION_MAX_DURATION) {
+        return Err(PythiaError::InvalidSessionDuration.into());
+    }
+
+    Sessions::create(
+        ic_cdk::caller(),
+        &address,
+        permissions,
+        nat::to_u64(&duration),
+    )
+}
+
+/// End a session. Without a principal the caller's session is ended,
+/// otherwise the session must belong to the same address as the caller's one.
+///
+/// # Arguments
+///
+/// * `principal` - Principal of the session to end, can be omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub fn logout(principal: Option<Principal>) -> Result<(), String> {
+    _logout(principal).map_err(|e| format!("failed to logout: {e:?}"))
+}
+
+#[inline]
+fn _logout(principal: Option<Principal>) -> Result<()> {
+    let caller = ic_cdk::caller();
+    let Some(principal) = principal.filter(|principal| *principal != caller) else {
+        Sessions::remove(&caller);
+        return Ok(());
+    };
+
+    let address = Sessions::get(&caller)?.address;
+    if Sessions::get(&principal)?.address != address {
+        return Err(PythiaError::SessionDoesNotExist.into());
+    }
+
+    Sessions::remove(&principal);
+    Ok(())
+}
+
+/// Get active sessions of the address the caller's session is bound to
+///
+/// # Returns
+///
+/// Returns a result with the sessions
+#[query]
+pub fn list_sessions() -> Result<Vec<Session>, String> {
+    _list_sessions().map_err(|e| format!("failed to list sessions: {e:?}"))
+}
+
+#[inline]
+fn _list_sessions() -> Result<Vec<Session>> {
+    let address = Sessions::get(&ic_cdk::caller())?.address;
+
+    Ok(Sessions::get_by_address(&address))
+}
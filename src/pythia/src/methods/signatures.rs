@@ -1,7 +1,7 @@
 use std::str::FromStr;
 
 use anyhow::Result;
-use candid::Nat;
+use candid::{Nat, Principal};
 use ic_cdk::{
     api::management_canister::ecdsa::{EcdsaCurve, EcdsaKeyId, SignWithEcdsaArgument},
     query, update,
@@ -12,7 +12,7 @@ use time::OffsetDateTime;
 
 use crate::{
     clone_with_state,
-    types::errors::PythiaError,
+    types::{errors::PythiaError, siwe::SiweNonces},
     utils::{
         address, canister,
         signature::{get_eth_v, sign},
@@ -191,3 +191,31 @@ async fn siwe_verify_signed_message(message: String, signature: String) -> Resul
 
     Ok(())
 }
+
+/// Issue a nonce for a SIWE message. The nonce is valid for a limited time,
+/// can be used only once and only by the caller it's issued to.
+///
+/// # Returns
+///
+/// Returns a result with the nonce
+#[update]
+async fn get_siwe_nonce() -> Result<String, String> {
+    _get_siwe_nonce()
+        .await
+        .map_err(|e| format!("failed to issue a SIWE nonce: {e:?}"))
+}
+
+#[inline]
+async fn _get_siwe_nonce() -> Result<String> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(PythiaError::AnonymousPrincipal.into());
+    }
+
+    let rand_bytes = crate::utils::rand().await?;
+    let nonce = hex::encode(&rand_bytes[..16]);
+
+    SiweNonces::issue(&nonce, caller)?;
+
+    Ok(nonce)
+}
@@ -4,19 +4,27 @@ use candid::Nat;
 use ic_cdk::{query, update};
 
 use crate::{
+    clone_with_state,
     jobs::publisher,
     log,
     types::{
         balance::Balances,
+        chains::Chains,
+        logger::SERVICE_ACCOUNTS,
+        operators::{Actor, Operators},
         pagination::{Pagination, PaginationResult},
+        runway::{self, GasPriceHistory, RunwayParams, SubscriptionRunway},
+        service_accounts::ServiceAccounts,
+        session::{Authorization, Sessions},
+        siwe::SiweAction,
         subscription::{
             GetSubscriptionsFilter, Subscription, Subscriptions, SubsribeRequest,
             UpdateSubscriptionRequest,
         },
         timer::Timer,
-        whitelist, SUPER_MSG, SUPER_SIG, SUPER_USER,
+        whitelist,
     },
-    utils::{siwe, validator},
+    utils::{address, validator},
     PythiaError,
 };
 
@@ -31,29 +39,45 @@ use crate::{
 /// A result with a subscription id
 #[update]
 pub async fn subscribe(req: SubsribeRequest) -> Result<Nat, String> {
-    _subscribe(req)
+    let auth = Authorization::Siwe {
+        msg: req.msg.clone(),
+        sig: req.sig.clone(),
+    };
+    _subscribe(auth, req)
+        .await
+        .map_err(|e| format!("failed to subsribe: {e:?}"))
+}
+
+/// Create a new subscription for the address of the caller's session.
+///
+/// # Arguments
+///
+/// * `req` - The SubscribeRequest candid type, `msg` and `sig` are ignored.
+///
+/// # Returns
+///
+/// A result with a subscription id
+#[update]
+pub async fn session_subscribe(req: SubsribeRequest) -> Result<Nat, String> {
+    _subscribe(Authorization::Session, req)
         .await
         .map_err(|e| format!("failed to subsribe: {e:?}"))
 }
 
 #[inline]
-async fn _subscribe(req: SubsribeRequest) -> Result<Nat> {
-    let address = if req.msg == SUPER_MSG && req.sig == SUPER_SIG {
-        log!("Creating a subscription for the super user {SUPER_USER}");
-        SUPER_USER.to_string()
-    } else {
-        siwe::siwe_recover(&req.msg, &req.sig)
-            .await
-            .context(PythiaError::UnableToRecoverAddress)?
-    };
+async fn _subscribe(auth: Authorization, req: SubsribeRequest) -> Result<Nat> {
+    let address = authorize(&auth, SiweAction::Subscribe, &req.chain_id).await?;
 
     if !whitelist::is_whitelisted(&address) {
         return Err(PythiaError::UserIsNotWhitelisted.into());
     }
-    if !Balances::is_sufficient(&req.chain_id, &address)? {
+    if Chains::is_decommissioning(&req.chain_id) {
+        return Err(PythiaError::ChainIsDecommissioning.into());
+    }
+    if !Balances::is_sufficient(&req.chain_id, &address).await? {
         return Err(PythiaError::InsufficientBalance.into());
     }
-    Subscriptions::check_limits(&address)?;
+    Subscriptions::check_limits(&address, &req.chain_id)?;
 
     let id = Subscriptions::add(req, &address)
         .await
@@ -97,6 +121,53 @@ pub fn get_subscription(chain_id: Nat, id: Nat) -> Result<Subscription, String>
     Subscriptions::get(&chain_id, &id).map_err(|e| format!("{e:?}"))
 }
 
+/// Estimate remaining executions and time until the balance runs out for every user's subscription on the chain
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `address` - Address of the user, for example 0x1234567890abcdef1234567890abcdef12345678
+///
+/// # Returns
+///
+/// Returns a result with the runway of every subscription, it's projected from the highest of the latest
+/// gas prices, fee and token rates seen by the publisher on the chain
+#[query]
+pub fn get_runway(chain_id: Nat, address: String) -> Result<Vec<SubscriptionRunway>, String> {
+    _get_runway(chain_id, address).map_err(|e| format!("failed to get the runway: {e:?}"))
+}
+
+#[inline]
+fn _get_runway(chain_id: Nat, address: String) -> Result<Vec<SubscriptionRunway>> {
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    let history = GasPriceHistory::get(&chain_id).context(PythiaError::GasPriceHistoryIsEmpty)?;
+    let gas_price = history
+        .max_gas_price()
+        .context(PythiaError::GasPriceHistoryIsEmpty)?;
+
+    let subs = Subscriptions::get_all(Some(GetSubscriptionsFilter {
+        owner: Some(address.clone()),
+        chain_ids: Some(vec![chain_id.clone()]),
+        ..Default::default()
+    }));
+
+    let balance = Balances::get_available_with_rates(&chain_id, &address, &history.token_rates)
+        .context(PythiaError::UnableToGetBalance)?;
+    let params = RunwayParams {
+        gas_price,
+        fee: whitelist::fee(&address, &history.fee),
+        min_balance: Chains::get_min_balance(&chain_id)?,
+        timer_frequency: clone_with_state!(timer_frequency),
+        warning_threshold: clone_with_state!(runway_warning_threshold),
+    };
+
+    Ok(runway::estimate(
+        &subs.iter().collect::<Vec<_>>(),
+        &balance,
+        &params,
+    ))
+}
+
 /// Stop a subscription
 ///
 /// # Arguments
@@ -116,32 +187,38 @@ pub async fn stop_subscription(
     msg: String,
     sig: String,
 ) -> Result<(), String> {
-    _stop_subscription(chain_id, sub_id, msg, sig)
+    _stop_subscription(chain_id, sub_id, Authorization::Siwe { msg, sig })
+        .await
+        .map_err(|e| format!("failed to stop a subscription: {e:?}"))
+}
+
+/// Stop a subscription of the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `sub_id` - The subscription id
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_stop_subscription(chain_id: Nat, sub_id: Nat) -> Result<(), String> {
+    _stop_subscription(chain_id, sub_id, Authorization::Session)
         .await
         .map_err(|e| format!("failed to stop a subscription: {e:?}"))
 }
 
 #[inline]
-pub async fn _stop_subscription(
-    chain_id: Nat,
-    sub_id: Nat,
-    msg: String,
-    sig: String,
-) -> Result<()> {
-    let address = if msg == SUPER_MSG && sig == SUPER_SIG {
-        log!("Stopping a subscription for the super user {SUPER_USER}");
-        SUPER_USER.to_string()
-    } else {
-        siwe::siwe_recover(&msg, &sig)
-            .await
-            .context(PythiaError::UnableToRecoverAddress)?
-    };
+pub async fn _stop_subscription(chain_id: Nat, sub_id: Nat, auth: Authorization) -> Result<()> {
+    let actor = actor(&auth, SiweAction::StopSubscription, &chain_id).await?;
+    let owner = Subscriptions::get(&chain_id, &sub_id)?.owner;
 
-    if !whitelist::is_whitelisted(&address) {
+    if !whitelist::is_whitelisted(&owner) {
         return Err(PythiaError::UserIsNotWhitelisted.into());
     }
 
-    Subscriptions::stop(&chain_id, &address, &sub_id)
+    Subscriptions::stop(&chain_id, &actor, &sub_id)
         .context(PythiaError::UnableToStopSubscription)?;
 
     log!("[SUBSCRIPTIONS] stopped, id: {sub_id}");
@@ -167,35 +244,47 @@ pub async fn start_subscription(
     msg: String,
     sig: String,
 ) -> Result<(), String> {
-    _start_subscription(chain_id, sub_id, msg, sig)
+    _start_subscription(chain_id, sub_id, Authorization::Siwe { msg, sig })
+        .await
+        .map_err(|e| format!("failed to start a subscription: {e:?}"))
+}
+
+/// Start a subscription of the address of the caller's session
+///
+/// # Arguments
+///
+/// * `chain_id` - Unique identifier of the chain, for example Ethereum Mainnet is 1
+/// * `sub_id` - The subscription id
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_start_subscription(chain_id: Nat, sub_id: Nat) -> Result<(), String> {
+    _start_subscription(chain_id, sub_id, Authorization::Session)
         .await
         .map_err(|e| format!("failed to start a subscription: {e:?}"))
 }
 
 #[inline]
-pub async fn _start_subscription(
-    chain_id: Nat,
-    sub_id: Nat,
-    msg: String,
-    sig: String,
-) -> Result<()> {
-    let address = if msg == SUPER_MSG && sig == SUPER_SIG {
-        log!("Starting a subscription for the super user {SUPER_USER}");
-        SUPER_USER.to_string()
-    } else {
-        siwe::siwe_recover(&msg, &sig)
-            .await
-            .context(PythiaError::UnableToRecoverAddress)?
-    };
+pub async fn _start_subscription(chain_id: Nat, sub_id: Nat, auth: Authorization) -> Result<()> {
+    let actor = actor(&auth, SiweAction::StartSubscription, &chain_id).await?;
 
-    if !whitelist::is_whitelisted(&address) {
+    start_checked(&chain_id, &sub_id, &actor).await
+}
+
+/// Start the subscription if its owner is whitelisted and the balance is sufficient
+pub async fn start_checked(chain_id: &Nat, sub_id: &Nat, actor: &Actor) -> Result<()> {
+    let owner = Subscriptions::get(chain_id, sub_id)?.owner;
+
+    if !whitelist::is_whitelisted(&owner) {
         return Err(PythiaError::UserIsNotWhitelisted.into());
     }
-    if !Balances::is_sufficient(&chain_id, &address)? {
+    if !Balances::is_sufficient(chain_id, &owner).await? {
         return Err(PythiaError::InsufficientBalance.into());
     }
 
-    Subscriptions::start(&chain_id, &address, &sub_id)
+    Subscriptions::start(chain_id, actor, sub_id)
         .context(PythiaError::UnableToStartSubscription)?;
 
     if !Timer::is_active() {
@@ -217,26 +306,40 @@ pub async fn _start_subscription(
 /// Returns a result that can contain an error message
 #[update]
 pub async fn update_subscription(req: UpdateSubscriptionRequest) -> Result<(), String> {
-    _update_subscription(req)
+    let auth = Authorization::Siwe {
+        msg: req.msg.clone(),
+        sig: req.sig.clone(),
+    };
+    _update_subscription(auth, req)
+        .await
+        .map_err(|e| format!("failed to update a subscription: {e:?}"))
+}
+
+/// Update a subscription of the address of the caller's session
+///
+/// # Arguments
+///
+/// * `req` - The UpdateSubscriptionRequest candid type, `msg` and `sig` are ignored.
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+pub async fn session_update_subscription(req: UpdateSubscriptionRequest) -> Result<(), String> {
+    _update_subscription(Authorization::Session, req)
         .await
         .map_err(|e| format!("failed to update a subscription: {e:?}"))
 }
 
 #[inline]
-async fn _update_subscription(req: UpdateSubscriptionRequest) -> Result<()> {
-    let address = if req.msg == SUPER_MSG && req.sig == SUPER_SIG {
-        log!("Updating subscription for the super user {SUPER_USER}");
-        SUPER_USER.to_string()
-    } else {
-        siwe::siwe_recover(&req.msg, &req.sig)
-            .await
-            .context(PythiaError::UnableToRecoverAddress)?
-    };
-    if !whitelist::is_whitelisted(&address) {
+async fn _update_subscription(auth: Authorization, req: UpdateSubscriptionRequest) -> Result<()> {
+    let actor = actor(&auth, SiweAction::UpdateSubscription, &req.chain_id).await?;
+    let owner = Subscriptions::get(&req.chain_id, &req.id)?.owner;
+    if !whitelist::is_whitelisted(&owner) {
         return Err(PythiaError::UserIsNotWhitelisted.into());
     }
 
-    Subscriptions::update(&req, &address)
+    Subscriptions::update(&req, &actor)
         .await
         .context(PythiaError::UnableToUpdateSubscription)?;
 
@@ -306,3 +409,33 @@ pub fn _remove_subscription(id: Nat) -> Result<()> {
     log!("[SUBSCRIPTIONS] removed, id: {}", id);
     Ok(())
 }
+
+/// Resolves the address the call acts for, a service account acts for its owner.
+async fn authorize(auth: &Authorization, action: SiweAction, chain_id: &Nat) -> Result<String> {
+    let caller = ic_cdk::caller();
+    if let Some(owner) = ServiceAccounts::get_owner(&caller) {
+        log!("[{SERVICE_ACCOUNTS}] {caller} acted for {owner}: {action:?} on chain {chain_id}");
+        return Ok(owner);
+    }
+
+    auth.address(action, chain_id).await
+}
+
+/// Resolves who manages a subscription, a principal operator without a session
+/// acts through the session endpoints by its principal only.
+async fn actor(auth: &Authorization, action: SiweAction, chain_id: &Nat) -> Result<Actor> {
+    let principal = ic_cdk::caller();
+    if let Authorization::Session = auth {
+        if Sessions::get(&principal).is_err() && Operators::is_principal_operator(&principal) {
+            return Ok(Actor {
+                address: None,
+                principal,
+            });
+        }
+    }
+
+    Ok(Actor {
+        address: Some(authorize(auth, action, chain_id).await?),
+        principal,
+    })
+}
@@ -1,3 +1,4 @@
+use candid::Nat;
 use ic_cdk::{query, update};
 
 use anyhow::{Context, Result};
@@ -6,39 +7,101 @@ use crate::{
     log,
     types::{
         errors::PythiaError,
+        pagination::{Pagination, PaginationResult},
+        roles::Role,
         subscription::Subscriptions,
-        whitelist::{self, Whitelist},
+        whitelist::{self, WhitelistEntry, WhitelistEntryRequest, WhitelistTier, WhitelistTiers},
     },
     utils::{address, validator},
 };
 
 /// Add an address to the whitelist
 ///
+/// Requires the whitelist manager role.
+///
 /// # Arguments
 ///
 /// * `address` - Address to add to the whitelist
+/// * `note` - Why the address is allowed, can be omitted
+/// * `expires_at` - Time in seconds after which the address is removed and its subscriptions are stopped, can be omitted
 ///
 /// # Returns
 ///
 /// Returns a result that can contain an error message
 #[update]
-fn add_to_whitelist(address: String) -> Result<(), String> {
-    _add_to_whitelist(address).map_err(|e| format!("failed to add to the whitelist: {e:?}"))
+fn add_to_whitelist(
+    address: String,
+    note: Option<String>,
+    expires_at: Option<Nat>,
+) -> Result<(), String> {
+    _add_to_whitelist(address, note, expires_at)
+        .map_err(|e| format!("failed to add to the whitelist: {e:?}"))
 }
 
 #[inline]
-fn _add_to_whitelist(address: String) -> Result<()> {
-    validator::caller()?;
+fn _add_to_whitelist(address: String, note: Option<String>, expires_at: Option<Nat>) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
 
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
-    whitelist::add(&address);
+    let req = WhitelistEntryRequest {
+        address: address.clone(),
+        note,
+        expires_at,
+        tier: None,
+    };
+    if whitelist::add(&[req], ic_cdk::caller())?.is_empty() {
+        return Err(PythiaError::AddressIsAlreadyWhitelisted.into());
+    }
 
     log!("[WHITELIST] address added to the whitelist: {address}");
     Ok(())
 }
 
+/// Add addresses to the whitelist, the already whitelisted ones are skipped.
+/// Nothing is added if any of the entries is invalid.
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `entries` - Addresses with their metadata
+///
+/// # Returns
+///
+/// Returns a result with the added addresses
+#[update]
+fn bulk_add_to_whitelist(entries: Vec<WhitelistEntryRequest>) -> Result<Vec<String>, String> {
+    _bulk_add_to_whitelist(entries).map_err(|e| format!("failed to add to the whitelist: {e:?}"))
+}
+
+#[inline]
+fn _bulk_add_to_whitelist(entries: Vec<WhitelistEntryRequest>) -> Result<Vec<String>> {
+    validator::role(Role::WhitelistManager)?;
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            Ok(WhitelistEntryRequest {
+                address: address::normalize(&entry.address)?,
+                ..entry
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .context(PythiaError::InvalidAddressFormat)?;
+
+    let added = whitelist::add(&entries, ic_cdk::caller())?;
+
+    log!(
+        "[WHITELIST] addresses added to the whitelist: {}",
+        added.len()
+    );
+    Ok(added)
+}
+
 /// Remove an address from the whitelist
 ///
+/// Requires the whitelist manager role.
+///
 /// # Arguments
 ///
 /// * `address` - Address to remove from the whitelist
@@ -54,7 +117,7 @@ fn remove_from_whitelist(address: String) -> Result<(), String> {
 
 #[inline]
 fn _remove_from_whitelist(address: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::WhitelistManager)?;
 
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
     whitelist::remove(&address);
@@ -67,24 +130,27 @@ fn _remove_from_whitelist(address: String) -> Result<()> {
 
 /// Blacklist an address
 ///
+/// Requires the whitelist manager role.
+///
 /// # Arguments
 ///
 /// * `address` - Address to blacklist
+/// * `reason` - Why the address is blacklisted, can be omitted
 ///
 /// # Returns
 ///
 /// Returns a result that can contain an error message
 #[update]
-fn blacklist(address: String) -> Result<(), String> {
-    _blacklist(address).map_err(|e| format!("failed to blacklist user: {e:?}"))
+fn blacklist(address: String, reason: Option<String>) -> Result<(), String> {
+    _blacklist(address, reason).map_err(|e| format!("failed to blacklist user: {e:?}"))
 }
 
 #[inline]
-fn _blacklist(address: String) -> Result<()> {
-    validator::caller()?;
+fn _blacklist(address: String, reason: Option<String>) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
 
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
-    whitelist::blacklist(&address);
+    whitelist::blacklist(&address, reason);
     Subscriptions::stop_all(None, vec![], Some(address.clone()))
         .context(PythiaError::UnableToStopSubscriptions)?;
 
@@ -94,6 +160,8 @@ fn _blacklist(address: String) -> Result<()> {
 
 /// Unblacklist an address
 ///
+/// Requires the whitelist manager role.
+///
 /// # Arguments
 ///
 /// * `address` - Address to unblacklist
@@ -108,7 +176,7 @@ fn unblacklist(address: String) -> Result<(), String> {
 
 #[inline]
 fn _unblacklist(address: String) -> Result<()> {
-    validator::caller()?;
+    validator::role(Role::WhitelistManager)?;
 
     let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
     whitelist::unblacklist(&address);
@@ -140,16 +208,130 @@ fn _is_whitelisted(address: String) -> Result<bool> {
 
 /// Get the whitelist
 ///
+/// Requires the support role.
+///
+/// # Arguments
+///
+/// * `pagination` - Pagination options, can be omitted. Entries are sorted by address
+///
 /// # Returns
 ///
 /// Returns the GetWhiteListResponse
 #[query]
-fn get_whitelist() -> Result<Whitelist, String> {
-    _get_whitelist().map_err(|e| format!("failed to get the whitelist: {e:?}"))
+fn get_whitelist(
+    pagination: Option<Pagination>,
+) -> Result<PaginationResult<WhitelistEntry>, String> {
+    _get_whitelist(pagination).map_err(|e| format!("failed to get the whitelist: {e:?}"))
+}
+
+#[inline]
+fn _get_whitelist(pagination: Option<Pagination>) -> Result<PaginationResult<WhitelistEntry>> {
+    validator::role(Role::Support)?;
+
+    let entries = whitelist::get_list();
+    Ok(match pagination {
+        Some(pagination) => pagination.paginate(entries),
+        None => entries.into(),
+    })
+}
+
+/// Export the whole whitelist with the metadata of the entries
+///
+/// Requires the support role.
+///
+/// # Returns
+///
+/// Returns a result with the whitelist entries sorted by address
+#[query]
+fn export_whitelist() -> Result<Vec<WhitelistEntry>, String> {
+    _export_whitelist().map_err(|e| format!("failed to export the whitelist: {e:?}"))
 }
 
 #[inline]
-fn _get_whitelist() -> Result<Whitelist> {
-    validator::caller()?;
+fn _export_whitelist() -> Result<Vec<WhitelistEntry>> {
+    validator::role(Role::Support)?;
+
     Ok(whitelist::get_list())
 }
+
+/// Create or replace a whitelist tier
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `tier` - Tier with its limits, an existing tier with the same name is replaced
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+fn set_whitelist_tier(tier: WhitelistTier) -> Result<(), String> {
+    _set_whitelist_tier(tier).map_err(|e| format!("failed to set the whitelist tier: {e:?}"))
+}
+
+#[inline]
+fn _set_whitelist_tier(tier: WhitelistTier) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
+
+    WhitelistTiers::set(tier)
+}
+
+/// Remove a whitelist tier, it must not be assigned to any address
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `name` - Name of the tier
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+fn remove_whitelist_tier(name: String) -> Result<(), String> {
+    _remove_whitelist_tier(name).map_err(|e| format!("failed to remove the whitelist tier: {e:?}"))
+}
+
+#[inline]
+fn _remove_whitelist_tier(name: String) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
+
+    WhitelistTiers::remove(&name)
+}
+
+/// Assign a tier to a whitelisted address
+///
+/// Requires the whitelist manager role.
+///
+/// # Arguments
+///
+/// * `address` - Whitelisted address
+/// * `tier` - Name of the tier, the global limits are applied if it's omitted
+///
+/// # Returns
+///
+/// Returns a result that can contain an error message
+#[update]
+fn assign_whitelist_tier(address: String, tier: Option<String>) -> Result<(), String> {
+    _assign_whitelist_tier(address, tier)
+        .map_err(|e| format!("failed to assign the whitelist tier: {e:?}"))
+}
+
+#[inline]
+fn _assign_whitelist_tier(address: String, tier: Option<String>) -> Result<()> {
+    validator::role(Role::WhitelistManager)?;
+
+    let address = address::normalize(&address).context(PythiaError::InvalidAddressFormat)?;
+    whitelist::set_tier(&address, tier)
+}
+
+/// Get the whitelist tiers
+///
+/// # Returns
+///
+/// Returns the tiers with their limits
+#[query]
+fn get_whitelist_tiers() -> Vec<WhitelistTier> {
+    WhitelistTiers::get_all()
+}
@@ -1,26 +1,37 @@
 use std::{collections::HashMap, str::FromStr, time::Duration};
 
 use crate::{
-    jobs::publisher,
+    jobs::{publisher, sweeper},
     log, metrics,
     types::{
+        access_requests::AccessRequests,
         balance::Balances,
-        chains::{Chain, Chains},
+        chains::{Chain, ChainDecommission, ChainFeeModel, ChainPause, ChainToken, Chains},
+        deposit_addresses::{DepositAddresses, Sweeps},
+        ledger::Ledger,
         methods::{ExecutionCondition, Method, MethodType},
+        operators::Operators,
+        roles::Roles,
+        runway::{GasPriceHistory, DEFAULT_RUNWAY_WARNING_THRESHOLD},
+        service_accounts::ServiceAccounts,
+        session::Sessions,
+        siwe::{SiweConfig, SiweNonces},
         subscription::{Subscription, SubscriptionStatus, Subscriptions, SubscriptionsIndexer},
         timer::Timer,
-        whitelist::Whitelist,
-        withdraw::WithdrawRequests,
+        whitelist::{Whitelist, WhitelistEntry, WhitelistTiers},
+        withdraw::{WithdrawRequest, WithdrawRequests, WithdrawRequestsIndexer, WithdrawStatus},
     },
     utils::{
+        self,
         canister::set_custom_panic_hook,
         metrics::{Metric, Metrics, METRICS},
+        nat,
     },
     State, STATE,
 };
 use candid::{CandidType, Nat, Principal};
 use ic_cdk::{post_upgrade, pre_upgrade, storage};
-use ic_cdk_timers::set_timer;
+use ic_cdk_timers::{set_timer, set_timer_interval};
 use ic_utils::{logger, monitor};
 use serde::{Deserialize, Serialize};
 
@@ -76,6 +87,7 @@ pub struct OldSubscription {
     pub frequency: Option<Nat>,
     pub method: OldMethod,
     pub status: SubscriptionStatus,
+    pub max_gas_price: Option<Nat>,
 }
 
 impl From<OldSubscription> for Subscription {
@@ -100,6 +112,7 @@ impl From<OldSubscription> for Subscription {
             contract_addr: old_subscription.contract_addr,
             method: old_subscription.method.into(),
             status: old_subscription.status,
+            max_gas_price: old_subscription.max_gas_price,
         };
 
         new
@@ -130,6 +143,11 @@ pub struct OldChain {
     pub fee: Option<Nat>,
     pub symbol: Option<String>,
     pub multicall_contract: Option<String>,
+    pub tokens: Option<Vec<ChainToken>>,
+    pub pause: Option<ChainPause>,
+    pub decommission: Option<ChainDecommission>,
+    pub max_gas_price: Option<Nat>,
+    pub fee_model: Option<ChainFeeModel>,
 }
 
 impl From<OldChain> for Chain {
@@ -143,6 +161,11 @@ impl From<OldChain> for Chain {
             symbol: old_chain.symbol,
             multicall_contract: old_chain.multicall_contract,
             errors_count: 0,
+            tokens: old_chain.tokens,
+            pause: old_chain.pause,
+            decommission: old_chain.decommission,
+            max_gas_price: old_chain.max_gas_price,
+            fee_model: old_chain.fee_model,
         }
     }
 }
@@ -163,6 +186,113 @@ impl From<OldChains> for Chains {
     }
 }
 
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct OldWithdrawRequest {
+    pub id: Option<Nat>,
+    pub owner: Option<String>,
+    pub amount: Nat,
+    pub receiver: String,
+    pub token: Option<String>,
+    pub status: Option<WithdrawStatus>,
+    pub charged: Option<Nat>,
+    pub fee: Option<Nat>,
+    pub batch_id: Option<Nat>,
+    pub stopped_subscriptions: Option<Vec<Nat>>,
+    pub attempts: Option<u32>,
+    pub created_at: Option<Nat>,
+    pub updated_at: Option<Nat>,
+}
+
+/// chain id => withdraw requests
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct OldWithdrawRequests(pub HashMap<Nat, Vec<OldWithdrawRequest>>);
+
+impl OldWithdrawRequests {
+    /// Requests queued before they had ids get new ones from the `indexer`
+    fn into_withdraw_requests(self, indexer: &mut WithdrawRequestsIndexer) -> WithdrawRequests {
+        WithdrawRequests(
+            self.0
+                .into_iter()
+                .map(|(chain_id, reqs)| {
+                    let reqs = reqs
+                        .into_iter()
+                        .map(|req| {
+                            let id = req.id.unwrap_or_else(|| {
+                                indexer.0 += 1;
+                                indexer.0.clone()
+                            });
+
+                            WithdrawRequest {
+                                // earlier requests are treated as withdrawals of their own
+                                batch_id: req.batch_id.unwrap_or(id.clone()),
+                                id,
+                                chain_id: chain_id.clone(),
+                                owner: req.owner.unwrap_or_default(),
+                                fee: req.fee.unwrap_or_else(|| {
+                                    req.charged
+                                        .as_ref()
+                                        .map(|charged| nat::saturating_sub(charged, &req.amount))
+                                        .unwrap_or_default()
+                                }),
+                                charged: req.charged.unwrap_or(req.amount.clone()),
+                                stopped_subscriptions: req
+                                    .stopped_subscriptions
+                                    .unwrap_or_default(),
+                                attempts: req.attempts.unwrap_or_default(),
+                                amount: req.amount,
+                                receiver: req.receiver,
+                                token: req.token,
+                                status: req.status.unwrap_or_default(),
+                                created_at: req.created_at.unwrap_or_default(),
+                                updated_at: req.updated_at.unwrap_or_default(),
+                            }
+                        })
+                        .collect();
+
+                    (chain_id, reqs)
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct OldWhitelistEntry {
+    pub address: String,
+    pub is_blacklisted: bool,
+    pub tier: Option<String>,
+}
+
+/// The whitelist was a list that could contain the same address several times
+pub type OldWhitelist = Vec<OldWhitelistEntry>;
+
+/// The metadata of the old entries is unknown, they are attributed to the canister at the upgrade time
+fn migrate_whitelist(old_whitelist: OldWhitelist) -> Whitelist {
+    let added_at = Nat::from(utils::time::in_seconds());
+    let mut whitelist = Whitelist::default();
+    for old_entry in old_whitelist {
+        let entry = whitelist
+            .0
+            .entry(old_entry.address.clone())
+            .or_insert_with(|| WhitelistEntry {
+                address: old_entry.address,
+                is_blacklisted: false,
+                tier: None,
+                added_by: ic_cdk::id(),
+                added_at: added_at.clone(),
+                note: None,
+                expires_at: None,
+                blacklist_reason: None,
+            });
+
+        // a blacklisted duplicate keeps the address blacklisted
+        entry.is_blacklisted |= old_entry.is_blacklisted;
+        entry.tier = entry.tier.take().or(old_entry.tier);
+    }
+
+    whitelist
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType, Default)]
 pub struct OldState {
     #[deprecated]
@@ -178,18 +308,38 @@ pub struct OldState {
     pub subs_limit_total: Nat,
     pub pma: Option<String>,
     pub balances: Balances,
-    pub withdraw_requests: WithdrawRequests,
+    pub withdraw_requests: OldWithdrawRequests,
     pub subscriptions: OldSubscriptions,
     pub timer_frequency: Nat,
     pub subscriptions_indexer: SubscriptionsIndexer,
     #[deprecated]
     pub is_timer_active: bool,
     pub timer: Option<Timer>,
-    pub whitelist: Whitelist,
+    pub whitelist: Option<OldWhitelist>,
+    pub deposit_addresses: Option<DepositAddresses>,
+    pub ledger: Option<Ledger>,
+    pub runway_warning_threshold: Option<Nat>,
+    pub withdraw_requests_indexer: Option<WithdrawRequestsIndexer>,
+    pub siwe_nonces: Option<SiweNonces>,
+    pub siwe_config: Option<SiweConfig>,
+    pub sessions: Option<Sessions>,
+    pub service_accounts: Option<ServiceAccounts>,
+    pub roles: Option<Roles>,
+    pub operators: Option<Operators>,
+    pub whitelist_tiers: Option<WhitelistTiers>,
+    pub whitelist_entries: Option<Whitelist>,
+    pub access_requests: Option<AccessRequests>,
+    pub sweeps: Option<Sweeps>,
+    pub gas_price_history: Option<GasPriceHistory>,
 }
 
 impl From<OldState> for State {
     fn from(old_state: OldState) -> Self {
+        let mut withdraw_requests_indexer = old_state.withdraw_requests_indexer.unwrap_or_default();
+        let withdraw_requests = old_state
+            .withdraw_requests
+            .into_withdraw_requests(&mut withdraw_requests_indexer);
+
         State {
             initialized: old_state.initialized,
             chains: old_state.chains.into(),
@@ -200,14 +350,32 @@ impl From<OldState> for State {
             subs_limit_total: old_state.subs_limit_total,
             pma: old_state.pma,
             balances: old_state.balances,
-            withdraw_requests: old_state.withdraw_requests,
+            withdraw_requests,
             subscriptions: old_state.subscriptions.into(),
             timer_frequency: old_state.timer_frequency,
             subscriptions_indexer: old_state.subscriptions_indexer,
             timer: old_state.timer,
-            whitelist: old_state.whitelist,
             controllers: old_state.controllers,
             is_timer_active: old_state.is_timer_active,
+            deposit_addresses: old_state.deposit_addresses.unwrap_or_default(),
+            ledger: old_state.ledger.unwrap_or_default(),
+            runway_warning_threshold: old_state
+                .runway_warning_threshold
+                .unwrap_or(Nat::from(DEFAULT_RUNWAY_WARNING_THRESHOLD)),
+            withdraw_requests_indexer,
+            siwe_nonces: old_state.siwe_nonces.unwrap_or_default(),
+            siwe_config: old_state.siwe_config.unwrap_or_default(),
+            sessions: old_state.sessions.unwrap_or_default(),
+            service_accounts: old_state.service_accounts.unwrap_or_default(),
+            roles: old_state.roles.unwrap_or_default(),
+            operators: old_state.operators.unwrap_or_default(),
+            whitelist_tiers: old_state.whitelist_tiers.unwrap_or_default(),
+            whitelist_entries: old_state
+                .whitelist_entries
+                .unwrap_or_else(|| migrate_whitelist(old_state.whitelist.unwrap_or_default())),
+            access_requests: old_state.access_requests.unwrap_or_default(),
+            sweeps: old_state.sweeps.unwrap_or_default(),
+            gas_price_history: old_state.gas_price_history.unwrap_or_default(),
         }
     }
 }
@@ -222,6 +390,7 @@ pub struct OldMetrics {
     pub SYBIL_OUTCALLS: Option<Metric>,
     pub SUCCESSFUL_SYBIL_OUTCALLS: Option<Metric>,
     pub CYCLES: Option<Metric>,
+    pub DEFERRED_SUBSCRIPTIONS: Option<Metric>,
 }
 
 impl From<OldMetrics> for Metrics {
@@ -234,6 +403,9 @@ impl From<OldMetrics> for Metrics {
             SYBIL_OUTCALLS: value.SYBIL_OUTCALLS.unwrap_or_default(),
             SUCCESSFUL_SYBIL_OUTCALLS: value.SUCCESSFUL_SYBIL_OUTCALLS.unwrap_or_default(),
             CYCLES: value.CYCLES.unwrap_or_default(),
+            DEFERRED_SUBSCRIPTIONS: value.DEFERRED_SUBSCRIPTIONS.unwrap_or_else(|| {
+                METRICS.with(|metrics| metrics.borrow().DEFERRED_SUBSCRIPTIONS.clone())
+            }),
         }
     }
 }
@@ -265,6 +437,10 @@ fn post_upgrade() {
     monitor::post_upgrade_stable_data(monitor_data);
 
     let timer_id = set_timer(Duration::from_secs(10), publisher::execute);
+    set_timer_interval(
+        Duration::from_secs(sweeper::SWEEP_INTERVAL),
+        sweeper::execute,
+    );
     let timer = Timer {
         id: serde_json::to_string(&timer_id).expect("should be valid timer id"),
         is_active: true,
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{log, utils::time, PythiaError, STATE};
+
+use super::{logger::ACCESS_REQUESTS, whitelist};
+
+/// Maximum number of applications waiting for a review
+pub const MAX_PENDING_ACCESS_REQUESTS: usize = 1_000;
+/// Maximum number of applications of a caller waiting for a review
+pub const MAX_PENDING_ACCESS_REQUESTS_PER_CALLER: usize = 3;
+/// Maximum length of the contact and the note of an application
+pub const MAX_ACCESS_REQUEST_FIELD_LENGTH: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum AccessRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Application of an address to be whitelisted
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct AccessRequest {
+    pub address: String,
+    /// How to reach the applicant, e.g. an email or a telegram handle
+    pub contact: String,
+    pub note: Option<String>,
+    pub status: AccessRequestStatus,
+    pub created_at: Nat,
+    /// Principal that submitted the application, `None` for the ones submitted before it was recorded
+    pub requested_by: Option<Principal>,
+    pub reviewed_by: Option<Principal>,
+    pub reviewed_at: Option<Nat>,
+    /// Reason of the rejection
+    pub review_note: Option<String>,
+}
+
+/// address => the latest application of the address
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct AccessRequests(pub HashMap<String, AccessRequest>);
+
+impl AccessRequests {
+    /// Record a pending application. An applicant can apply again after the
+    /// rejection or after the approved address is removed from the whitelist,
+    /// blacklisted addresses can't apply.
+    pub fn add(
+        address: &str,
+        contact: &str,
+        note: Option<String>,
+        requested_by: Principal,
+    ) -> Result<AccessRequest> {
+        if contact.is_empty()
+            || contact.len() > MAX_ACCESS_REQUEST_FIELD_LENGTH
+            || note
+                .as_ref()
+                .map_or(false, |note| note.len() > MAX_ACCESS_REQUEST_FIELD_LENGTH)
+        {
+            return Err(PythiaError::InvalidAccessRequest.into());
+        }
+        if whitelist::is_whitelisted(address) {
+            return Err(PythiaError::AddressIsAlreadyWhitelisted.into());
+        }
+        if whitelist::is_blacklisted(address) {
+            return Err(PythiaError::AddressIsBlacklisted.into());
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let requests = &mut state.access_requests.0;
+
+            if requests
+                .get(address)
+                .map_or(false, |req| req.status == AccessRequestStatus::Pending)
+            {
+                return Err(PythiaError::AccessRequestIsAlreadyPending.into());
+            }
+
+            let pending = requests
+                .values()
+                .filter(|req| req.status == AccessRequestStatus::Pending)
+                .collect::<Vec<_>>();
+            if pending.len() >= MAX_PENDING_ACCESS_REQUESTS
+                || pending
+                    .iter()
+                    .filter(|req| req.requested_by == Some(requested_by))
+                    .count()
+                    >= MAX_PENDING_ACCESS_REQUESTS_PER_CALLER
+            {
+                return Err(PythiaError::TooManyPendingAccessRequests.into());
+            }
+
+            let request = AccessRequest {
+                address: address.to_string(),
+                contact: contact.to_string(),
+                note,
+                status: AccessRequestStatus::Pending,
+                created_at: Nat::from(time::in_seconds()),
+                requested_by: Some(requested_by),
+                reviewed_by: None,
+                reviewed_at: None,
+                review_note: None,
+            };
+            requests.insert(address.to_string(), request.clone());
+
+            log!("[{ACCESS_REQUESTS}] Access requested: address = {address}, requested_by = {requested_by}");
+            Ok(request)
+        })
+    }
+
+    pub fn get(address: &str) -> Result<AccessRequest> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .access_requests
+                .0
+                .get(address)
+                .cloned()
+                .context(PythiaError::AccessRequestDoesNotExist)
+        })
+    }
+
+    /// Applications sorted by creation time, optionally only with the given status
+    pub fn get_all(status: Option<AccessRequestStatus>) -> Vec<AccessRequest> {
+        STATE.with(|state| {
+            let mut requests = state
+                .borrow()
+                .access_requests
+                .0
+                .values()
+                .filter(|req| status.map_or(true, |status| req.status == status))
+                .cloned()
+                .collect::<Vec<_>>();
+            requests.sort_by(|l, r| l.created_at.cmp(&r.created_at));
+
+            requests
+        })
+    }
+
+    /// Close a pending application with the `Approved` or `Rejected` status
+    pub fn review(
+        address: &str,
+        status: AccessRequestStatus,
+        reviewer: Principal,
+        review_note: Option<String>,
+    ) -> Result<AccessRequest> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let request = state
+                .access_requests
+                .0
+                .get_mut(address)
+                .context(PythiaError::AccessRequestDoesNotExist)?;
+
+            if request.status != AccessRequestStatus::Pending {
+                return Err(PythiaError::AccessRequestIsNotPending.into());
+            }
+
+            request.status = status;
+            request.reviewed_by = Some(reviewer);
+            request.reviewed_at = Some(Nat::from(time::in_seconds()));
+            request.review_note = review_note;
+
+            log!("[{ACCESS_REQUESTS}] Access request reviewed: address = {address}, status = {status:?}, reviewer = {reviewer}");
+            Ok(request.clone())
+        })
+    }
+}
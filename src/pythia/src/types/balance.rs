@@ -9,12 +9,16 @@ use crate::{
     dig, dig_mut, log,
     utils::{
         address,
+        erc20::{self, TokenRate},
         multicall::{BASE_GAS, GAS_PER_TRANSFER},
+        nat,
     },
     STATE,
 };
 
-use super::{chains::Chains, errors::PythiaError, logger::BALANCES};
+use super::{
+    chains::Chains, errors::PythiaError, ledger::LedgerEntryKind, logger::BALANCES, whitelist,
+};
 
 const ETH_TRANSFER_GAS_LIMIT: u64 = BASE_GAS + GAS_PER_TRANSFER;
 
@@ -22,6 +26,43 @@ const ETH_TRANSFER_GAS_LIMIT: u64 = BASE_GAS + GAS_PER_TRANSFER;
 pub struct UserBalance {
     pub amount: Nat,
     pub nonces: Vec<Nat>,
+    /// token address => amount
+    pub tokens: Option<HashMap<String, Nat>>,
+    /// subscription id => amount in the native coin reserved for the in-flight execution
+    pub reserved: Option<HashMap<Nat, Nat>>,
+}
+
+impl UserBalance {
+    /// Native amount plus the token amounts converted to the native coin
+    pub fn value_in_native(&self, rates: &HashMap<String, TokenRate>) -> Nat {
+        let tokens_value = self
+            .tokens
+            .iter()
+            .flatten()
+            .filter_map(|(token, amount)| rates.get(token).map(|rate| rate.to_native(amount)))
+            .fold(Nat::from(0), |sum, value| sum + value);
+
+        self.amount.clone() + tokens_value
+    }
+
+    pub fn reserved_total(&self) -> Nat {
+        self.reserved
+            .iter()
+            .flatten()
+            .fold(Nat::from(0), |sum, (_, amount)| sum + amount.clone())
+    }
+
+    /// Value in the native coin that is not reserved by in-flight executions
+    pub fn available_in_native(&self, rates: &HashMap<String, TokenRate>) -> Nat {
+        nat::saturating_sub(&self.value_in_native(rates), &self.reserved_total())
+    }
+
+    pub fn has_tokens(&self) -> bool {
+        self.tokens
+            .iter()
+            .flatten()
+            .any(|(_, amount)| *amount > Nat::from(0))
+    }
 }
 
 /// chain id => user's public key => PUB (Pythia User Balance)
@@ -29,19 +70,114 @@ pub struct UserBalance {
 pub struct Balances(pub HashMap<Nat, HashMap<String, UserBalance>>);
 
 impl Balances {
-    pub fn get_value_for_withdraw(chain_id: &Nat, address: &str, gas_price: &Nat) -> Result<Nat> {
+    /// Deduct the withdrawals from the available balance, an amount of `None` withdraws the whole
+    /// available balance. Returns the deducted amounts, the share of the batch gas is paid from every
+    /// amount when the withdrawal is sent
+    pub fn get_values_for_withdraw(
+        chain_id: &Nat,
+        address: &str,
+        gas_price: &Nat,
+        withdrawals: &[(String, Option<Nat>)],
+    ) -> Result<Vec<Nat>> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
             let balance = dig_mut!(state, balances, chain_id, address)
                 .context(PythiaError::BalanceDoesNotExist)?;
 
+            // funds reserved by in-flight executions stay on the balance until they are settled
+            let available = nat::saturating_sub(&balance.amount, &balance.reserved_total());
+            let amounts = withdrawals
+                .iter()
+                .map(|(_, amount)| amount.clone().unwrap_or(available.clone()))
+                .collect::<Vec<Nat>>();
+
+            let total = amounts
+                .iter()
+                .fold(Nat::from(0), |sum, amount| sum + amount.clone());
+            if total > available {
+                return Err(PythiaError::InsufficientBalance.into());
+            }
+
             let gas = Nat::from(ETH_TRANSFER_GAS_LIMIT) * gas_price.clone();
-            if balance.amount < gas {
+            if amounts.iter().any(|amount| *amount < gas) {
                 return Err(anyhow!("not enough funds to pay for gas"));
             }
-            let value = balance.amount.clone() - gas;
-            balance.amount = Nat::from(0);
-            Ok(value)
+
+            let mut new_balance = balance.amount.clone();
+            balance.amount -= total;
+
+            for ((receiver, _), amount) in withdrawals.iter().zip(&amounts) {
+                new_balance -= amount.clone();
+                state.ledger.record(
+                    chain_id,
+                    address,
+                    LedgerEntryKind::Withdrawal {
+                        receiver: receiver.clone(),
+                    },
+                    None,
+                    amount,
+                    false,
+                    &new_balance,
+                );
+            }
+
+            Ok(amounts)
+        })
+    }
+
+    /// Deduct the whole token balance except the part needed for the funds reserved by in-flight executions
+    /// that the rest of the balance can't cover. Returns the amount to transfer, the `gas` is paid from it
+    pub fn get_value_for_token_withdraw(
+        chain_id: &Nat,
+        address: &str,
+        token: &str,
+        gas: &Nat,
+        receiver: &str,
+        rates: &HashMap<String, TokenRate>,
+    ) -> Result<Nat> {
+        let rate = rates
+            .get(token)
+            .context(PythiaError::UnableToGetTokenRate)?;
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let balance = dig_mut!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?;
+            let token_amount = balance
+                .tokens
+                .as_ref()
+                .and_then(|tokens| tokens.get(token))
+                .cloned()
+                .context(PythiaError::TokenDoesNotExist)?;
+
+            let rest_value = nat::saturating_sub(
+                &balance.value_in_native(rates),
+                &rate.to_native(&token_amount),
+            );
+            let kept = rate.to_token(&nat::saturating_sub(&balance.reserved_total(), &rest_value));
+            let withdrawn = nat::saturating_sub(&token_amount, &kept);
+            if withdrawn < *gas {
+                return Err(anyhow!("not enough tokens to pay for gas"));
+            }
+
+            let new_balance = token_amount - withdrawn.clone();
+            balance
+                .tokens
+                .get_or_insert_with(HashMap::new)
+                .insert(token.to_string(), new_balance.clone());
+
+            state.ledger.record(
+                chain_id,
+                address,
+                LedgerEntryKind::Withdrawal {
+                    receiver: receiver.to_string(),
+                },
+                Some(token.to_string()),
+                &withdrawn,
+                false,
+                &new_balance,
+            );
+
+            Ok(withdrawn - gas.clone())
         })
     }
 
@@ -89,7 +225,12 @@ impl Balances {
         })
     }
 
-    pub fn add_amount(chain_id: &Nat, address: &str, amount: &Nat) -> Result<()> {
+    pub fn add_amount(
+        chain_id: &Nat,
+        address: &str,
+        amount: &Nat,
+        kind: LedgerEntryKind,
+    ) -> Result<()> {
         let address = address::normalize(address)?;
         STATE.with(|state| {
             let mut state = state.borrow_mut();
@@ -101,6 +242,11 @@ impl Balances {
                 .get_mut(&address)
                 .context(PythiaError::BalanceDoesNotExist)?;
             balance.amount += amount.clone();
+            let new_balance = balance.amount.clone();
+
+            state
+                .ledger
+                .record(chain_id, &address, kind, None, amount, true, &new_balance);
             log!(
                 "[{BALANCES}] Balance amount added: chain_id = {}, address = {}, amount = {}",
                 chain_id,
@@ -111,6 +257,63 @@ impl Balances {
         })
     }
 
+    pub fn add_token_amount(
+        chain_id: &Nat,
+        address: &str,
+        token: &str,
+        amount: &Nat,
+        kind: LedgerEntryKind,
+    ) -> Result<()> {
+        let address = address::normalize(address)?;
+        let token = address::normalize(token)?;
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let balance = state
+                .balances
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInBalances)?
+                .get_mut(&address)
+                .context(PythiaError::BalanceDoesNotExist)?;
+            let token_amount = balance
+                .tokens
+                .get_or_insert_with(HashMap::new)
+                .entry(token.clone())
+                .or_default();
+            *token_amount += amount.clone();
+            let new_balance = token_amount.clone();
+
+            state.ledger.record(
+                chain_id,
+                &address,
+                kind,
+                Some(token.clone()),
+                amount,
+                true,
+                &new_balance,
+            );
+            log!(
+                "[{BALANCES}] Token amount added: chain_id = {}, address = {}, token = {}, amount = {}",
+                chain_id,
+                address,
+                token,
+                amount
+            );
+            Ok(())
+        })
+    }
+
+    pub fn get_tokens(chain_id: &Nat, address: &str) -> Result<HashMap<String, Nat>> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            Ok(dig!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?
+                .tokens
+                .clone()
+                .unwrap_or_default())
+        })
+    }
+
     pub fn get(chain_id: &Nat, address: &str) -> Result<Nat> {
         STATE.with(|state| {
             let state = state.borrow();
@@ -133,36 +336,292 @@ impl Balances {
         })
     }
 
+    /// All the balances on the chain, address => balance
+    pub fn get_chain_balances(chain_id: &Nat) -> Result<HashMap<String, UserBalance>> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .balances
+                .0
+                .get(chain_id)
+                .cloned()
+                .context(PythiaError::ChainDoesNotExistInBalances)
+        })
+    }
+
+    /// Remove the balances of the chain, the amounts left on them are recorded in the ledger
     pub fn remove_chain(chain_id: &Nat) -> Result<()> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            if state.balances.0.remove(chain_id).is_some() {
-                log!("[{BALANCES}] Chain removed: {chain_id}");
-            } else {
+            let Some(balances) = state.balances.0.remove(chain_id) else {
                 log!("[{BALANCES}] Chain does not exist: {chain_id}");
+                return Ok(());
+            };
+
+            for (address, balance) in balances {
+                let tokens = balance.tokens.into_iter().flatten().map(|(t, a)| (Some(t), a));
+                for (token, amount) in std::iter::once((None, balance.amount)).chain(tokens) {
+                    #[allow(clippy::cmp_owned)]
+                    if amount == Nat::from(0) {
+                        continue;
+                    }
+
+                    state.ledger.record(
+                        chain_id,
+                        &address,
+                        LedgerEntryKind::ChainRemoved,
+                        token.clone(),
+                        &amount,
+                        false,
+                        &Nat::from(0),
+                    );
+                    log!(
+                        "[{BALANCES}] Balance left on the removed chain: chain_id = {}, address = {}, token = {:?}, amount = {}",
+                        chain_id,
+                        address,
+                        token,
+                        amount
+                    );
+                }
             }
 
+            log!("[{BALANCES}] Chain removed: {chain_id}");
             Ok(())
         })
     }
 
-    pub fn is_sufficient(chain_id: &Nat, address: &str) -> Result<bool> {
-        let balance = STATE.with(|state| {
+    /// Rates of the tokens to convert the user's balance, empty if the balance has no tokens
+    pub async fn token_rates(chain_id: &Nat, address: &str) -> Result<HashMap<String, TokenRate>> {
+        let has_tokens = STATE.with(|state| {
             let state = state.borrow();
             dig!(state, balances, chain_id, address)
                 .context(PythiaError::BalanceDoesNotExist)
-                .map(|balance| balance.amount.clone())
+                .map(|balance| balance.has_tokens())
         })?;
 
-        Ok(balance >= Chains::get_min_balance(chain_id)?)
+        if has_tokens {
+            erc20::rates(chain_id).await
+        } else {
+            Ok(HashMap::new())
+        }
+    }
+
+    pub async fn is_sufficient(chain_id: &Nat, address: &str) -> Result<bool> {
+        Ok(Self::get_available(chain_id, address).await? >= Chains::get_min_balance(chain_id)?)
     }
 
-    pub fn reduce(chain_id: &Nat, address: &str, amount: &Nat) -> Result<()> {
+    /// Value of the balance in the native coin that is not reserved by in-flight executions
+    pub async fn get_available(chain_id: &Nat, address: &str) -> Result<Nat> {
+        let rates = Self::token_rates(chain_id, address).await?;
+
+        Self::get_available_with_rates(chain_id, address, &rates)
+    }
+
+    pub fn get_available_with_rates(
+        chain_id: &Nat,
+        address: &str,
+        rates: &HashMap<String, TokenRate>,
+    ) -> Result<Nat> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            Ok(dig!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?
+                .available_in_native(rates))
+        })
+    }
+
+    /// The available balance covers the min balance plus the next execution of every active subscription of the user
+    pub fn is_covering_subscriptions(
+        chain_id: &Nat,
+        address: &str,
+        gas_price: &Nat,
+        fee: &Nat,
+        rates: &HashMap<String, TokenRate>,
+    ) -> Result<bool> {
+        let fee = whitelist::fee(address, fee);
+        let projected_costs = STATE.with(|state| {
+            state
+                .borrow()
+                .subscriptions
+                .0
+                .get(chain_id)
+                .into_iter()
+                .flatten()
+                .filter(|sub| sub.owner == address && sub.status.is_active)
+                .fold(Nat::from(0), |sum, sub| {
+                    sum + sub.method.gas_limit.clone() * gas_price.clone() + fee.clone()
+                })
+        });
+
+        Ok(Self::get_available_with_rates(chain_id, address, rates)?
+            >= Chains::get_min_balance(chain_id)? + projected_costs)
+    }
+
+    /// Reserve `amount` for the execution of the subscription, fails if the available balance can't cover it
+    pub fn reserve(
+        chain_id: &Nat,
+        address: &str,
+        sub_id: &Nat,
+        amount: &Nat,
+        rates: &HashMap<String, TokenRate>,
+    ) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let balance = dig_mut!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?;
+
+            let mut reserved = balance.reserved.take().unwrap_or_default();
+            reserved.remove(sub_id);
+            balance.reserved = Some(reserved);
+
+            if balance.available_in_native(rates) < *amount {
+                return Err(PythiaError::InsufficientBalance.into());
+            }
+
+            balance
+                .reserved
+                .get_or_insert_with(HashMap::new)
+                .insert(sub_id.clone(), amount.clone());
+
+            log!(
+                "[{BALANCES}] Balance reserved: chain_id = {}, address = {}, sub_id = {}, amount = {}",
+                chain_id,
+                address,
+                sub_id,
+                amount
+            );
+            Ok(())
+        })
+    }
+
+    /// Release the reservation of the subscription without charging anything
+    pub fn release(chain_id: &Nat, address: &str, sub_id: &Nat) -> Result<()> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
             let balance = dig_mut!(state, balances, chain_id, address)
                 .context(PythiaError::BalanceDoesNotExist)?;
+
+            if let Some(reserved) = balance.reserved.as_mut() {
+                reserved.remove(sub_id);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Reduce the native balance by `amount`, the rest is taken from the tokens using the `rates`
+    /// in the order the tokens are configured on the chain.
+    /// Nothing is charged if the balance can't cover the whole amount
+    pub fn charge(
+        chain_id: &Nat,
+        address: &str,
+        amount: &Nat,
+        rates: &HashMap<String, TokenRate>,
+        kind: LedgerEntryKind,
+    ) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let tokens_order = state
+                .chains
+                .0
+                .get(chain_id)
+                .and_then(|chain| chain.tokens.clone())
+                .unwrap_or_default();
+            let balance = dig_mut!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?;
+
+            if balance.value_in_native(rates) < *amount {
+                return Err(PythiaError::InsufficientBalance.into());
+            }
+
+            // (token, charged amount, balance after the charge)
+            let mut charges = vec![];
+
+            let mut left = nat::saturating_sub(amount, &balance.amount);
+            let native_balance = nat::saturating_sub(&balance.amount, amount);
+            charges.push((
+                None,
+                balance.amount.clone() - native_balance.clone(),
+                native_balance.clone(),
+            ));
+            balance.amount = native_balance;
+
+            for chain_token in tokens_order {
+                #[allow(clippy::cmp_owned)]
+                if left == Nat::from(0) {
+                    break;
+                }
+
+                let token = chain_token.address;
+                let (Some(rate), Some(token_amount)) = (
+                    rates.get(&token),
+                    balance
+                        .tokens
+                        .as_mut()
+                        .and_then(|tokens| tokens.get_mut(&token)),
+                ) else {
+                    continue;
+                };
+
+                let needed = rate.to_token(&left);
+                let charged = if *token_amount >= needed {
+                    left = Nat::from(0);
+                    needed
+                } else {
+                    left = nat::saturating_sub(&left, &rate.to_native(token_amount));
+                    token_amount.clone()
+                };
+                *token_amount -= charged.clone();
+                charges.push((Some(token), charged, token_amount.clone()));
+            }
+
+            for (token, charged, new_balance) in charges {
+                #[allow(clippy::cmp_owned)]
+                if charged == Nat::from(0) {
+                    continue;
+                }
+
+                state.ledger.record(
+                    chain_id,
+                    address,
+                    kind.clone(),
+                    token,
+                    &charged,
+                    false,
+                    &new_balance,
+                );
+            }
+
+            log!(
+                "[{BALANCES}] Balance charged: chain_id = {}, address = {}, amount = {}",
+                chain_id,
+                address,
+                amount
+            );
+
+            Ok(())
+        })
+    }
+
+    pub fn reduce(
+        chain_id: &Nat,
+        address: &str,
+        amount: &Nat,
+        kind: LedgerEntryKind,
+    ) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let balance = dig_mut!(state, balances, chain_id, address)
+                .context(PythiaError::BalanceDoesNotExist)?;
+            if balance.amount < *amount {
+                return Err(PythiaError::InsufficientBalance.into());
+            }
             balance.amount -= amount.clone();
+            let new_balance = balance.amount.clone();
+
+            state
+                .ledger
+                .record(chain_id, address, kind, None, amount, false, &new_balance);
 
             log!(
                 "[{BALANCES}] Balance amount reduced: chain_id = {}, address = {}, amount = {}",
@@ -179,7 +638,18 @@ impl Balances {
             let mut state = state.borrow_mut();
             let balance = dig_mut!(state, balances, chain_id, address)
                 .context(PythiaError::BalanceDoesNotExist)?;
+            let cleared = balance.amount.clone();
             balance.amount = Nat::from(0);
+
+            state.ledger.record(
+                chain_id,
+                address,
+                LedgerEntryKind::AdminClear,
+                None,
+                &cleared,
+                false,
+                &Nat::from(0),
+            );
             log!(
                 "[{BALANCES}] Balance cleared: chain_id = {}, address = {}",
                 chain_id,
@@ -1,15 +1,74 @@
 use std::collections::HashMap;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use candid::{CandidType, Nat};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 use super::{errors::PythiaError, logger::CHAINS};
-use crate::{log, types::subscription::Subscriptions, STATE};
+use crate::{
+    log,
+    utils::{address, nat, time},
+    STATE,
+};
 
-// After ${CHAIN_ERROR_LIMIT} errors, all subscription will be stopped
+// After ${CHAIN_ERROR_LIMIT} errors, the chain will be paused until the probe succeeds
 const CHAIN_ERRORS_LIMIT: u8 = 3;
+const CHAIN_PROBE_INITIAL_DELAY: u64 = 60 * 5;
+const CHAIN_PROBE_MAX_DELAY: u64 = 60 * 60 * 6;
+/// Version of the `ChainsExport` document, bumped on incompatible changes
+pub const CHAINS_EXPORT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType, PartialEq)]
+pub enum ChainPauseReason {
+    /// Paused by a controller, resumed only by a controller
+    Manual,
+    /// Paused after ${CHAIN_ERRORS_LIMIT} errors in a row, resumed when the chain's RPC responds again
+    Errors,
+}
+
+/// How the transaction cost is computed on the chain
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType, Default, PartialEq)]
+pub enum ChainFeeModel {
+    /// `gas_price * used_gas`
+    #[default]
+    Plain,
+    /// Optimism, Base and other OP Stack rollups, the L1 data fee is taken from `GasPriceOracle.getL1Fee`
+    OpStack,
+    /// The L1 component is taken from `NodeInterface.gasEstimateL1Component`
+    Arbitrum,
+}
+
+/// The chain is being removed, its balances are withdrawn to the users' addresses first
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType, Default)]
+pub struct ChainDecommission {
+    pub started_at: Nat,
+    /// Withdrawal requests enqueued for the users' balances
+    pub withdrawal_ids: Vec<Nat>,
+    /// Addresses whose balances can't be withdrawn, they are written off in the ledger when the chain is removed
+    pub dust_balances: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType, Default)]
+pub struct DecommissionStatus {
+    pub started_at: Nat,
+    pub withdrawals_total: Nat,
+    pub withdrawals_confirmed: Nat,
+    pub withdrawals_pending: Nat,
+    /// Failed withdrawals are retried by the withdraw job until they are abandoned
+    pub withdrawals_failed: Nat,
+    pub dust_balances: Vec<String>,
+}
+
+/// Publishing is suspended for the chain, subscriptions keep their own state
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType)]
+pub struct ChainPause {
+    pub reason: ChainPauseReason,
+    pub paused_at: Nat,
+    /// Time of the next auto-resume probe, `None` for the manual pause
+    pub next_probe_at: Option<Nat>,
+    pub probe_delay: Option<Nat>,
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize, CandidType, Default)]
 pub struct Chain {
@@ -21,6 +80,29 @@ pub struct Chain {
     pub symbol: Option<String>,
     pub multicall_contract: Option<String>,
     pub errors_count: u8,
+    pub tokens: Option<Vec<ChainToken>>,
+    pub pause: Option<ChainPause>,
+    pub decommission: Option<ChainDecommission>,
+    /// Subscriptions on the chain are deferred while the network's gas price is above it
+    pub max_gas_price: Option<Nat>,
+    /// `None` is the plain fee model
+    pub fee_model: Option<ChainFeeModel>,
+}
+
+impl Chain {
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_some()
+    }
+}
+
+/// ERC-20 token accepted for deposits on a chain
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType, Default)]
+pub struct ChainToken {
+    pub address: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// Sybil feed with the rate of the chain's native coin in this token, e.g. "ETH/USD" for USDC
+    pub feed_id: String,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
@@ -44,6 +126,55 @@ pub struct ChainUpdator {
     pub multicall_contract: Option<String>,
 }
 
+/// Chain configuration without the runtime state (errors, pause, decommission)
+#[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
+pub struct ChainConfig {
+    pub chain_id: Nat,
+    pub rpc: String,
+    pub min_balance: Nat,
+    pub block_gas_limit: Nat,
+    pub fee: Nat,
+    pub symbol: String,
+    pub multicall_contract: String,
+    pub tokens: Vec<ChainToken>,
+    pub max_gas_price: Option<Nat>,
+    pub fee_model: ChainFeeModel,
+}
+
+impl From<Chain> for ChainConfig {
+    fn from(chain: Chain) -> Self {
+        Self {
+            chain_id: chain.chain_id,
+            rpc: chain.rpc,
+            min_balance: chain.min_balance,
+            block_gas_limit: chain.block_gas_limit,
+            fee: chain.fee.unwrap_or_default(),
+            symbol: chain.symbol.unwrap_or_default(),
+            multicall_contract: chain.multicall_contract.unwrap_or_default(),
+            tokens: chain.tokens.unwrap_or_default(),
+            max_gas_price: chain.max_gas_price,
+            fee_model: chain.fee_model.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
+pub struct ChainsExport {
+    pub version: u32,
+    pub chains: Vec<ChainConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType, PartialEq)]
+pub enum ChainsImportMode {
+    /// Add new chains and update the existing ones
+    #[default]
+    Upsert,
+    /// Only add new chains, fails if any of them already exists
+    CreateOnly,
+    /// Only update existing chains, fails if any of them doesn't exist
+    UpdateOnly,
+}
+
 /// Chain id => Chain
 #[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
 pub struct Chains(pub HashMap<Nat, Chain>);
@@ -64,6 +195,11 @@ impl Chains {
                     symbol: Some(req.symbol.clone()),
                     multicall_contract: Some(req.multicall_contract.clone()),
                     errors_count: 0,
+                    tokens: None,
+                    pause: None,
+                    decommission: None,
+                    max_gas_price: None,
+                    fee_model: None,
                 },
             );
         });
@@ -72,6 +208,114 @@ impl Chains {
         Ok(())
     }
 
+    pub fn export() -> ChainsExport {
+        let mut chains = Self::get_all()
+            .into_iter()
+            .map(ChainConfig::from)
+            .collect::<Vec<_>>();
+        chains.sort_by(|a, b| a.chain_id.cmp(&b.chain_id));
+
+        ChainsExport {
+            version: CHAINS_EXPORT_VERSION,
+            chains,
+        }
+    }
+
+    /// Check the whole document before anything is changed, returns the normalized configs
+    pub fn validate_import(doc: ChainsExport, mode: &ChainsImportMode) -> Result<Vec<ChainConfig>> {
+        if doc.version != CHAINS_EXPORT_VERSION {
+            return Err(PythiaError::UnsupportedChainsExportVersion(doc.version).into());
+        }
+
+        let mut configs: Vec<ChainConfig> = vec![];
+        for config in doc.chains {
+            let chain_id = config.chain_id.clone();
+            if configs.iter().any(|c| c.chain_id == chain_id) {
+                return Err(PythiaError::DuplicateChainInImport(chain_id).into());
+            }
+
+            let config = Self::validate_config(config, mode)
+                .with_context(|| format!("invalid chain config, chain_id = {chain_id}"))?;
+            configs.push(config);
+        }
+
+        Ok(configs)
+    }
+
+    fn validate_config(config: ChainConfig, mode: &ChainsImportMode) -> Result<ChainConfig> {
+        let is_exists = Self::is_exists(&config.chain_id);
+        match mode {
+            ChainsImportMode::CreateOnly if is_exists => {
+                return Err(PythiaError::ChainAlreadyExists.into())
+            }
+            ChainsImportMode::UpdateOnly if !is_exists => {
+                return Err(PythiaError::ChainDoesNotExist.into())
+            }
+            _ => {}
+        }
+        if Self::is_decommissioning(&config.chain_id) {
+            return Err(PythiaError::ChainIsDecommissioning.into());
+        }
+
+        #[allow(clippy::cmp_owned)]
+        if config.block_gas_limit == Nat::from(0) {
+            return Err(anyhow!("block gas limit should be positive"));
+        }
+
+        let rpc: Url = config.rpc.parse().context(PythiaError::InvalidChainRPC)?;
+        let mut tokens: Vec<ChainToken> = vec![];
+        for token in config.tokens {
+            let address = address::normalize(&token.address)?;
+            if tokens.iter().any(|t| t.address == address) {
+                return Err(PythiaError::TokenAlreadyExists.into());
+            }
+            tokens.push(ChainToken { address, ..token });
+        }
+
+        Ok(ChainConfig {
+            rpc: rpc.to_string(),
+            multicall_contract: address::normalize(&config.multicall_contract)?,
+            tokens,
+            ..config
+        })
+    }
+
+    /// Insert new chains and replace the config of the existing ones, keeping their runtime state.
+    /// Returns the ids of the new chains
+    pub fn import(configs: Vec<ChainConfig>) -> Vec<Nat> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let mut new_chain_ids = vec![];
+            for config in configs {
+                let chain = state
+                    .chains
+                    .0
+                    .entry(config.chain_id.clone())
+                    .or_insert_with(|| {
+                        new_chain_ids.push(config.chain_id.clone());
+                        Chain {
+                            chain_id: config.chain_id.clone(),
+                            ..Default::default()
+                        }
+                    });
+
+                chain.rpc = config.rpc;
+                chain.min_balance = config.min_balance;
+                chain.block_gas_limit = config.block_gas_limit;
+                chain.fee = Some(config.fee);
+                chain.symbol = Some(config.symbol);
+                chain.multicall_contract = Some(config.multicall_contract);
+                chain.tokens = Some(config.tokens);
+                chain.max_gas_price = config.max_gas_price;
+                chain.fee_model = Some(config.fee_model);
+
+                log!("[{CHAINS}] Chain imported: chain_id = {}", config.chain_id);
+            }
+
+            new_chain_ids
+        })
+    }
+
     pub fn increment_error_count(id: Nat) -> Result<()> {
         let errors_count = STATE.with(|state| -> anyhow::Result<u8> {
             let mut state = state.borrow_mut();
@@ -81,18 +325,222 @@ impl Chains {
                 .get_mut(&id)
                 .ok_or(PythiaError::ChainDoesNotExist)?;
 
-            chain.errors_count += 1;
+            chain.errors_count = chain.errors_count.saturating_add(1);
             Ok(chain.errors_count)
         });
 
-        if errors_count? >= CHAIN_ERRORS_LIMIT {
-            Subscriptions::stop_all(Some(id.clone()), vec![], None)?;
-            log!("[{CHAINS}] Chain {} stopped due to errors", id);
+        if errors_count? >= CHAIN_ERRORS_LIMIT && !Self::get(&id)?.is_paused() {
+            Self::pause(&id, ChainPauseReason::Errors)?;
+            log!("[{CHAINS}] Chain {} paused due to errors", id);
         }
 
         Ok(())
     }
 
+    /// Suspend publishing for the chain, the error pause is probed with an exponential backoff
+    pub fn pause(id: &Nat, reason: ChainPauseReason) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            let now = time::in_seconds();
+            let (next_probe_at, probe_delay) = match reason {
+                ChainPauseReason::Manual => (None, None),
+                ChainPauseReason::Errors => (
+                    Some(Nat::from(now + CHAIN_PROBE_INITIAL_DELAY)),
+                    Some(Nat::from(CHAIN_PROBE_INITIAL_DELAY)),
+                ),
+            };
+
+            chain.pause = Some(ChainPause {
+                reason,
+                paused_at: Nat::from(now),
+                next_probe_at,
+                probe_delay,
+            });
+
+            log!("[{CHAINS}] Chain paused: chain_id = {}", id);
+            Ok(())
+        })
+    }
+
+    pub fn resume(id: &Nat) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            if chain.decommission.is_some() {
+                return Err(PythiaError::ChainIsDecommissioning.into());
+            }
+            if chain.pause.take().is_none() {
+                return Err(PythiaError::ChainIsNotPaused.into());
+            }
+            chain.errors_count = 0;
+
+            log!("[{CHAINS}] Chain resumed: chain_id = {}", id);
+            Ok(())
+        })
+    }
+
+    /// Pause the chain and mark it as decommissioning, does nothing if it's already decommissioning
+    pub fn start_decommission(id: &Nat) -> Result<()> {
+        if Self::is_decommissioning(id) {
+            return Ok(());
+        }
+
+        Self::pause(id, ChainPauseReason::Manual)?;
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            chain.decommission = Some(ChainDecommission {
+                started_at: Nat::from(time::in_seconds()),
+                ..Default::default()
+            });
+
+            log!("[{CHAINS}] Chain decommission started: chain_id = {}", id);
+            Ok(())
+        })
+    }
+
+    /// Add the enqueued withdrawals and replace the dust balances of the decommissioning chain
+    pub fn update_decommission(
+        id: &Nat,
+        withdrawal_ids: &[Nat],
+        dust_balances: Vec<String>,
+    ) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let decommission = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?
+                .decommission
+                .as_mut()
+                .ok_or(PythiaError::ChainIsNotDecommissioning)?;
+
+            decommission
+                .withdrawal_ids
+                .extend(withdrawal_ids.iter().cloned());
+            decommission.dust_balances = dust_balances;
+
+            Ok(())
+        })
+    }
+
+    pub fn set_max_gas_price(id: &Nat, max_gas_price: Option<Nat>) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            chain.max_gas_price = max_gas_price;
+            Ok(())
+        })
+    }
+
+    pub fn set_fee_model(id: &Nat, fee_model: ChainFeeModel) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            chain.fee_model = Some(fee_model);
+            Ok(())
+        })
+    }
+
+    pub fn is_decommissioning(id: &Nat) -> bool {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .chains
+                .0
+                .get(id)
+                .is_some_and(|chain| chain.decommission.is_some())
+        })
+    }
+
+    /// Postpone the next probe of the chain paused due to errors, the delay is doubled
+    pub fn postpone_probe(id: &Nat) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let pause = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?
+                .pause
+                .as_mut()
+                .ok_or(PythiaError::ChainIsNotPaused)?;
+
+            let delay = pause
+                .probe_delay
+                .as_ref()
+                .map(|delay| nat::to_u64(delay).saturating_mul(2))
+                .unwrap_or(CHAIN_PROBE_INITIAL_DELAY)
+                .min(CHAIN_PROBE_MAX_DELAY);
+
+            pause.probe_delay = Some(Nat::from(delay));
+            pause.next_probe_at = Some(Nat::from(time::in_seconds() + delay));
+
+            Ok(())
+        })
+    }
+
+    /// Chains paused due to errors whose probe time has come
+    pub fn get_due_for_probe() -> Vec<Nat> {
+        let now = Nat::from(time::in_seconds());
+        STATE.with(|state| {
+            state
+                .borrow()
+                .chains
+                .0
+                .values()
+                .filter(|chain| {
+                    chain.pause.as_ref().is_some_and(|pause| {
+                        pause.reason == ChainPauseReason::Errors
+                            && pause
+                                .next_probe_at
+                                .as_ref()
+                                .is_some_and(|next_probe_at| *next_probe_at <= now)
+                    })
+                })
+                .map(|chain| chain.chain_id.clone())
+                .collect()
+        })
+    }
+
+    pub fn is_paused(id: &Nat) -> bool {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .chains
+                .0
+                .get(id)
+                .is_some_and(|chain| chain.is_paused())
+        })
+    }
+
     pub fn reset_error_count(id: Nat) -> Result<()> {
         STATE.with(|state| -> anyhow::Result<()> {
             let mut state = state.borrow_mut();
@@ -159,6 +607,67 @@ impl Chains {
         })
     }
 
+    pub fn add_token(id: &Nat, token: ChainToken) -> Result<()> {
+        let address = address::normalize(&token.address)?;
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            let tokens = chain.tokens.get_or_insert_with(Vec::new);
+            if tokens.iter().any(|t| t.address == address) {
+                return Err(PythiaError::TokenAlreadyExists.into());
+            }
+
+            tokens.push(ChainToken { address, ..token });
+            Ok(())
+        })
+    }
+
+    pub fn remove_token(id: &Nat, token: &str) -> Result<()> {
+        let token = address::normalize(token)?;
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let chain = state
+                .chains
+                .0
+                .get_mut(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            let tokens = chain.tokens.get_or_insert_with(Vec::new);
+            let index = tokens
+                .iter()
+                .position(|t| t.address == token)
+                .ok_or(PythiaError::TokenDoesNotExist)?;
+            tokens.remove(index);
+
+            Ok(())
+        })
+    }
+
+    pub fn get_token(id: &Nat, token: &str) -> Result<Option<ChainToken>> {
+        let token = address::normalize(token)?;
+        Ok(Self::get_tokens(id)?
+            .into_iter()
+            .find(|t| t.address == token))
+    }
+
+    pub fn get_tokens(id: &Nat) -> Result<Vec<ChainToken>> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let chain = state
+                .chains
+                .0
+                .get(id)
+                .ok_or(PythiaError::ChainDoesNotExist)?;
+
+            Ok(chain.tokens.clone().unwrap_or_default())
+        })
+    }
+
     pub fn get(id: &Nat) -> Result<Chain> {
         STATE.with(|state| {
             state
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candid::{CandidType, Nat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log,
+    utils::{nat, time},
+    PythiaError, STATE,
+};
+
+use super::logger::SWEEPER;
+
+/// Seconds after which a sweep that didn't send its transaction is considered interrupted
+const SWEEP_LOCK_TIMEOUT: u64 = 60 * 10;
+
+/// user's address => deposit address derived for the user
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct DepositAddresses(pub HashMap<String, String>);
+
+impl DepositAddresses {
+    pub fn get(address: &str) -> Option<String> {
+        STATE.with(|state| state.borrow().deposit_addresses.0.get(address).cloned())
+    }
+
+    pub fn add(address: &str, deposit_address: &str) {
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .deposit_addresses
+                .0
+                .insert(address.to_string(), deposit_address.to_string());
+
+            log!(
+                "[{SWEEPER}] Deposit address added: address = {}, deposit_address = {}",
+                address,
+                deposit_address
+            );
+        })
+    }
+
+    pub fn get_all() -> Vec<(String, String)> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .deposit_addresses
+                .0
+                .iter()
+                .map(|(address, deposit_address)| (address.clone(), deposit_address.clone()))
+                .collect()
+        })
+    }
+}
+
+/// Transaction that moves the funds of a deposit address to the PMA
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct SweepTx {
+    pub tx_hash: String,
+    /// Nonce of the deposit address used by the transaction
+    pub nonce: Nat,
+    /// Value credited to the user's balance once the transaction is confirmed
+    pub value: Nat,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Sweep {
+    pub started_at: Nat,
+    /// `None` while the transaction isn't sent yet
+    pub tx: Option<SweepTx>,
+}
+
+/// chain id => user's address => sweep in progress, a deposit address is swept by one caller at a time
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Sweeps(pub HashMap<Nat, HashMap<String, Sweep>>);
+
+impl Sweeps {
+    /// Start a sweep of the user's deposit address. Returns the sent transaction
+    /// if the previous sweep isn't settled yet, it should be settled instead
+    pub fn lock(chain_id: &Nat, address: &str) -> Result<Option<SweepTx>> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let now = time::in_seconds();
+            let sweeps = state.sweeps.0.entry(chain_id.clone()).or_default();
+
+            if let Some(sweep) = sweeps.get(address) {
+                if sweep.tx.is_some() {
+                    return Ok(sweep.tx.clone());
+                }
+                if nat::to_u64(&sweep.started_at) + SWEEP_LOCK_TIMEOUT > now {
+                    return Err(PythiaError::SweepIsInProgress.into());
+                }
+            }
+
+            sweeps.insert(
+                address.to_string(),
+                Sweep {
+                    started_at: Nat::from(now),
+                    tx: None,
+                },
+            );
+
+            Ok(None)
+        })
+    }
+
+    /// Release the lock of the sweep that didn't send its transaction
+    pub fn unlock(chain_id: &Nat, address: &str) {
+        STATE.with(|state| {
+            if let Some(sweeps) = state.borrow_mut().sweeps.0.get_mut(chain_id) {
+                if sweeps.get(address).is_some_and(|sweep| sweep.tx.is_none()) {
+                    sweeps.remove(address);
+                }
+            }
+        })
+    }
+
+    pub fn set_sent(chain_id: &Nat, address: &str, tx: SweepTx) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let sweeps = state.sweeps.0.entry(chain_id.clone()).or_default();
+            let sweep = sweeps.entry(address.to_string()).or_default();
+            log!(
+                "[{SWEEPER}] Sweep sent: chain_id = {}, address = {}, tx_hash = {}",
+                chain_id,
+                address,
+                tx.tx_hash
+            );
+            sweep.tx = Some(tx);
+        })
+    }
+
+    /// Remove the sweep with the transaction, returns `false` if it's already settled
+    pub fn finish(chain_id: &Nat, address: &str, tx_hash: &str) -> bool {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let Some(sweeps) = state.sweeps.0.get_mut(chain_id) else {
+                return false;
+            };
+
+            let is_sent = sweeps
+                .get(address)
+                .and_then(|sweep| sweep.tx.as_ref())
+                .is_some_and(|tx| tx.tx_hash == tx_hash);
+            if is_sent {
+                sweeps.remove(address);
+            }
+
+            is_sent
+        })
+    }
+}
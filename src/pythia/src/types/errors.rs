@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use super::methods::ExecutionConditionError;
+use super::{methods::ExecutionConditionError, siwe::SiweError};
 
 #[derive(Error, Debug)]
 pub enum PythiaError {
@@ -48,6 +48,8 @@ pub enum PythiaError {
     UnableToIncreaseBalance,
     #[error("Unable to get gas price")]
     UnableToGetGasPrice,
+    #[error("No gas prices were seen by the publisher on the chain yet")]
+    GasPriceHistoryIsEmpty,
     #[error("Unable to get value for withdraw")]
     UnableToGetValueForWithdraw,
     #[error("Unable to add a new chain")]
@@ -94,6 +96,8 @@ pub enum PythiaError {
     UnableToUpdateSubscription,
     #[error("Unable to stop subscriptions")]
     UnableToStopSubscriptions,
+    #[error("Unable to start subscriptions")]
+    UnableToStartSubscriptions,
     #[error("Unable to remove subscriptions")]
     UnableToRemoveSubscriptions,
     #[error("Invalid contract ABI")]
@@ -162,6 +166,92 @@ pub enum PythiaError {
     ExecutionConditionError(#[from] ExecutionConditionError),
     #[error("Unable to estimate gas")]
     UnableToEstimateGas,
+    #[error("Token already exists")]
+    TokenAlreadyExists,
+    #[error("Token does not exist")]
+    TokenDoesNotExist,
+    #[error("Unable to get token rate")]
+    UnableToGetTokenRate,
+    #[error("Unable to get L1 fee")]
+    UnableToGetL1Fee,
+    #[error("Deposit address does not exist")]
+    DepositAddressDoesNotExist,
+    #[error("Sweep of the deposit address is in progress")]
+    SweepIsInProgress,
+    #[error("Withdraw request does not exist")]
+    WithdrawRequestDoesNotExist,
+    #[error("Withdraw request is already sent")]
+    WithdrawRequestIsAlreadySent,
+    #[error("Withdraw request is not failed")]
+    WithdrawRequestIsNotFailed,
+    #[error("Chain is not paused")]
+    ChainIsNotPaused,
+    #[error("Chain is decommissioning")]
+    ChainIsDecommissioning,
+    #[error("Chain is not decommissioning")]
+    ChainIsNotDecommissioning,
+    #[error("Unsupported chains export version: {0}")]
+    UnsupportedChainsExportVersion(u32),
+    #[error("Chain {0} is duplicated in the import")]
+    DuplicateChainInImport(candid::Nat),
+    #[error("Chain id mismatch: expected {expected}, RPC returned {actual}")]
+    ChainIdMismatch { expected: String, actual: String },
+    #[error("Multicall contract has no code at {0}")]
+    MulticallContractHasNoCode(String),
+    #[error("Multicall contract doesn't expose the {0} function")]
+    MulticallContractMissingFunction(String),
+    #[error("Block gas limit {configured} exceeds the chain's block gas limit {actual}")]
+    BlockGasLimitExceeded { configured: String, actual: String },
+    #[error("SIWE error: {0}")]
+    SiweError(#[from] SiweError),
+    #[error("Session does not exist")]
+    SessionDoesNotExist,
+    #[error("Session is expired")]
+    SessionIsExpired,
+    #[error("Session doesn't have the permission: {0}")]
+    SessionPermissionDenied(String),
+    #[error("Session must have at least one permission")]
+    SessionWithoutPermissions,
+    #[error("Invalid session duration")]
+    InvalidSessionDuration,
+    #[error("Anonymous principal is not allowed")]
+    AnonymousPrincipal,
+    #[error("Service account already exists")]
+    ServiceAccountAlreadyExists,
+    #[error("Service account does not exist")]
+    ServiceAccountDoesNotExist,
+    #[error("Caller doesn't have the {0} role")]
+    MissingRole(String),
+    #[error("Operator grant does not exist")]
+    OperatorGrantDoesNotExist,
+    #[error("Operator grant expiration is in the past")]
+    InvalidOperatorGrantExpiration,
+    #[error("Whitelist tier must have a name, positive limits and a fee multiplier from 1 to 100")]
+    InvalidWhitelistTier,
+    #[error("Whitelist tier does not exist")]
+    WhitelistTierDoesNotExist,
+    #[error("Whitelist tier is assigned to addresses")]
+    WhitelistTierIsAssigned,
+    #[error("Chain is not allowed by the whitelist tier")]
+    ChainIsNotAllowedByTier,
+    #[error("Frequency is lower than the whitelist tier minimum: {0}")]
+    FrequencyIsLowerThanTierMinimum(String),
+    #[error("Whitelist entry expiration is in the past")]
+    InvalidWhitelistExpiration,
+    #[error("Address is already whitelisted")]
+    AddressIsAlreadyWhitelisted,
+    #[error("Access request must have a contact, the contact and the note must not be too long")]
+    InvalidAccessRequest,
+    #[error("Access request is already pending")]
+    AccessRequestIsAlreadyPending,
+    #[error("Access request does not exist")]
+    AccessRequestDoesNotExist,
+    #[error("Access request is not pending")]
+    AccessRequestIsNotPending,
+    #[error("Too many pending access requests")]
+    TooManyPendingAccessRequests,
+    #[error("Address is blacklisted")]
+    AddressIsBlacklisted,
     #[error("Sign error: {0}")]
     SignError(String),
 }
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Nat};
+use serde::{Deserialize, Serialize};
+
+use crate::{utils::time, STATE};
+
+/// Number of the latest entries kept per user and chain, older entries are pruned
+pub const MAX_LEDGER_ENTRIES_PER_USER: usize = 1_000;
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub enum LedgerEntryKind {
+    Deposit {
+        tx_hash: Option<String>,
+    },
+    ExecutionCharge {
+        subscription_id: Nat,
+        tx_hash: Option<String>,
+    },
+    PlatformFee {
+        subscription_id: Nat,
+    },
+    Withdrawal {
+        receiver: String,
+    },
+    WithdrawalCancelled {
+        withdrawal_id: Nat,
+    },
+    /// Part of the estimated transfer gas that was not spent
+    WithdrawalRefund {
+        withdrawal_id: Nat,
+    },
+    AdminClear,
+    /// Balance left on the chain when it was removed
+    ChainRemoved,
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub id: Nat,
+    pub kind: LedgerEntryKind,
+    /// ERC-20 token address, `None` for the native coin
+    pub token: Option<String>,
+    pub amount: Nat,
+    pub is_credit: bool,
+    /// Balance of the asset after the entry was applied
+    pub balance: Nat,
+    pub timestamp: Nat,
+}
+
+/// chain id => user's address => balance history, only the latest `MAX_LEDGER_ENTRIES_PER_USER` entries are kept
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Ledger(pub HashMap<Nat, HashMap<String, Vec<LedgerEntry>>>);
+
+impl Ledger {
+    /// Should be called in the same state borrow as the balance change it describes.
+    /// Ids keep growing after the oldest entries are pruned
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        chain_id: &Nat,
+        address: &str,
+        kind: LedgerEntryKind,
+        token: Option<String>,
+        amount: &Nat,
+        is_credit: bool,
+        balance: &Nat,
+    ) {
+        let entries = self
+            .0
+            .entry(chain_id.clone())
+            .or_default()
+            .entry(address.to_string())
+            .or_default();
+
+        let id = entries
+            .last()
+            .map_or(Nat::from(0), |entry| entry.id.clone() + 1);
+        entries.push(LedgerEntry {
+            id,
+            kind,
+            token,
+            amount: amount.clone(),
+            is_credit,
+            balance: balance.clone(),
+            timestamp: Nat::from(time::in_seconds()),
+        });
+
+        if entries.len() > MAX_LEDGER_ENTRIES_PER_USER {
+            entries.drain(..entries.len() - MAX_LEDGER_ENTRIES_PER_USER);
+        }
+    }
+
+    pub fn get(chain_id: &Nat, address: &str) -> Vec<LedgerEntry> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .ledger
+                .0
+                .get(chain_id)
+                .and_then(|entries| entries.get(address))
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+}
@@ -7,3 +7,7 @@ pub const TIMER: &str = "TIMER";
 pub const WHITELIST: &str = "WHITELIST";
 pub const BALANCES: &str = "BALANCES";
 pub const SYBIL: &str = "SYBIL";
+pub const SWEEPER: &str = "SWEEPER";
+pub const SESSIONS: &str = "SESSIONS";
+pub const SERVICE_ACCOUNTS: &str = "SERVICE_ACCOUNTS";
+pub const ACCESS_REQUESTS: &str = "ACCESS_REQUESTS";
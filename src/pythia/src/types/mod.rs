@@ -1,16 +1,21 @@
+pub mod access_requests;
 pub mod asset_data;
 pub mod balance;
 pub mod chains;
+pub mod deposit_addresses;
 pub mod errors;
+pub mod ledger;
 pub mod logger;
 pub mod methods;
+pub mod operators;
 pub mod pagination;
+pub mod roles;
+pub mod runway;
+pub mod service_accounts;
+pub mod session;
+pub mod siwe;
 pub mod state;
 pub mod subscription;
 pub mod timer;
 pub mod whitelist;
 pub mod withdraw;
-
-pub const SUPER_MSG: &str = "investly";
-pub const SUPER_SIG: &str = "signed investly";
-pub const SUPER_USER: &str = "0x654dff41d51c230fa400205a633101c5c1f1969c";
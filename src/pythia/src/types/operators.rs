@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{log, utils::time, PythiaError, STATE};
+
+use super::logger::SUBSCRIPTION;
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum OperatorGrantee {
+    Address(String),
+    Principal(Principal),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum OperatorScope {
+    Global,
+    Chain(Nat),
+    Subscription { chain_id: Nat, id: Nat },
+}
+
+impl OperatorScope {
+    pub fn covers(&self, chain_id: &Nat, sub_id: &Nat) -> bool {
+        match self {
+            OperatorScope::Global => true,
+            OperatorScope::Chain(scope_chain_id) => scope_chain_id == chain_id,
+            OperatorScope::Subscription {
+                chain_id: scope_chain_id,
+                id,
+            } => scope_chain_id == chain_id && id == sub_id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct OperatorGrant {
+    pub grantee: OperatorGrantee,
+    pub scope: OperatorScope,
+    pub expires_at: Option<Nat>,
+    pub granted_at: Nat,
+}
+
+impl OperatorGrant {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.as_ref().map_or(false, |expires_at| {
+            *expires_at <= Nat::from(time::in_seconds())
+        })
+    }
+}
+
+/// Who manages a subscription: the address is absent when a principal
+/// operator acts without a session.
+#[derive(Clone, Debug)]
+pub struct Actor {
+    pub address: Option<String>,
+    pub principal: Principal,
+}
+
+impl Actor {
+    /// The canister acting on behalf of the owner
+    pub fn owner(address: &str) -> Self {
+        Self {
+            address: Some(address.to_string()),
+            principal: ic_cdk::id(),
+        }
+    }
+}
+
+/// owner => operators granted by the owner
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Operators(pub HashMap<String, Vec<OperatorGrant>>);
+
+impl Operators {
+    pub fn grant(
+        owner: &str,
+        grantee: OperatorGrantee,
+        scope: OperatorScope,
+        expires_at: Option<Nat>,
+    ) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let grants = state.operators.0.entry(owner.to_string()).or_default();
+            grants.retain(|grant| {
+                !grant.is_expired() && !(grant.grantee == grantee && grant.scope == scope)
+            });
+            grants.push(OperatorGrant {
+                grantee: grantee.clone(),
+                scope: scope.clone(),
+                expires_at,
+                granted_at: Nat::from(time::in_seconds()),
+            });
+
+            log!("[{SUBSCRIPTION}] Operator granted: owner = {owner}, grantee = {grantee:?}, scope = {scope:?}");
+        })
+    }
+
+    pub fn revoke(owner: &str, grantee: &OperatorGrantee, scope: &OperatorScope) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let grants = state
+                .operators
+                .0
+                .get_mut(owner)
+                .ok_or(PythiaError::OperatorGrantDoesNotExist)?;
+
+            let len = grants.len();
+            grants.retain(|grant| !(grant.grantee == *grantee && grant.scope == *scope));
+            if grants.len() == len {
+                return Err(PythiaError::OperatorGrantDoesNotExist.into());
+            }
+            if grants.is_empty() {
+                state.operators.0.remove(owner);
+            }
+
+            log!("[{SUBSCRIPTION}] Operator revoked: owner = {owner}, grantee = {grantee:?}, scope = {scope:?}");
+            Ok(())
+        })
+    }
+
+    pub fn get(owner: &str) -> Vec<OperatorGrant> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .operators
+                .0
+                .get(owner)
+                .map(|grants| {
+                    grants
+                        .iter()
+                        .filter(|grant| !grant.is_expired())
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn is_principal_operator(principal: &Principal) -> bool {
+        let grantee = OperatorGrantee::Principal(*principal);
+        STATE.with(|state| {
+            state
+                .borrow()
+                .operators
+                .0
+                .values()
+                .flatten()
+                .any(|grant| grant.grantee == grantee && !grant.is_expired())
+        })
+    }
+
+    /// Checks that the actor is the owner or an operator of the owner's subscription.
+    pub fn can_manage(&self, owner: &str, actor: &Actor, chain_id: &Nat, sub_id: &Nat) -> bool {
+        if actor.address.as_deref() == Some(owner) {
+            return true;
+        }
+
+        let Some(grants) = self.0.get(owner) else {
+            return false;
+        };
+
+        grants.iter().any(|grant| {
+            let is_grantee = match &grant.grantee {
+                OperatorGrantee::Address(address) => actor.address.as_ref() == Some(address),
+                OperatorGrantee::Principal(principal) => *principal == actor.principal,
+            };
+
+            is_grantee && !grant.is_expired() && grant.scope.covers(chain_id, sub_id)
+        })
+    }
+}
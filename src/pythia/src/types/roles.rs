@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{log, STATE};
+
+use super::logger::CONTROLLERS;
+
+/// Admin role of a principal. Controllers implicitly have every role.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, CandidType, Serialize, Deserialize)]
+pub enum Role {
+    /// Canister configuration, implies every other role
+    Owner,
+    /// Chains and the jobs operating them
+    ChainOperator,
+    /// Whitelist and blacklist
+    WhitelistManager,
+    /// Read-only access to the admin queries
+    Support,
+    /// Withdrawing the collected fees and clearing balances
+    Treasury,
+}
+
+/// principal => roles granted to the principal
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Roles(pub HashMap<Principal, Vec<Role>>);
+
+impl Roles {
+    pub fn grant(principal: Principal, role: Role) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let roles = state.roles.0.entry(principal).or_default();
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+
+            log!("[{CONTROLLERS}] Role {role:?} granted to {principal}");
+        })
+    }
+
+    pub fn revoke(principal: &Principal, role: Role) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if let Some(roles) = state.roles.0.get_mut(principal) {
+                roles.retain(|r| *r != role);
+                if roles.is_empty() {
+                    state.roles.0.remove(principal);
+                }
+            }
+
+            log!("[{CONTROLLERS}] Role {role:?} revoked from {principal}");
+        })
+    }
+
+    pub fn get_all() -> Vec<(Principal, Vec<Role>)> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .roles
+                .0
+                .iter()
+                .map(|(principal, roles)| (*principal, roles.clone()))
+                .collect()
+        })
+    }
+
+    /// Every role gives read-only access, the owner role gives access to everything.
+    pub fn has(principal: &Principal, role: Role) -> bool {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let Some(roles) = state.roles.0.get(principal) else {
+                return false;
+            };
+
+            roles.contains(&Role::Owner)
+                || roles.contains(&role)
+                || (role == Role::Support && !roles.is_empty())
+        })
+    }
+}
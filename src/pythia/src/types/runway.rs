@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Nat};
+use serde::{Deserialize, Serialize};
+
+use super::{methods::ExecutionCondition, subscription::Subscription};
+use crate::{
+    utils::{erc20::TokenRate, nat},
+    STATE,
+};
+
+/// One day
+pub const DEFAULT_RUNWAY_WARNING_THRESHOLD: u64 = 60 * 60 * 24;
+/// Number of the latest gas prices kept per chain
+const GAS_PRICE_HISTORY_SIZE: usize = 12;
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct SubscriptionRunway {
+    pub subscription_id: Nat,
+    /// Worst-case cost of a single execution in the native coin
+    pub execution_cost: Nat,
+    /// Expected seconds between executions
+    pub interval: Nat,
+    /// `None` if the balance is not spent
+    pub remaining_executions: Option<Nat>,
+    /// `None` if the balance is not spent
+    pub seconds_until_empty: Option<Nat>,
+    pub is_at_risk: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RunwayParams {
+    pub gas_price: Nat,
+    pub fee: Nat,
+    pub min_balance: Nat,
+    pub timer_frequency: Nat,
+    pub warning_threshold: Nat,
+}
+
+/// What the publisher saw on the chain, the runway is projected from it without outcalls
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct ChainGasPrices {
+    /// Network gas prices of the latest publisher ticks, the oldest first
+    pub gas_prices: Vec<Nat>,
+    pub fee: Nat,
+    pub token_rates: HashMap<String, TokenRate>,
+}
+
+impl ChainGasPrices {
+    /// The highest of the latest gas prices, so a short dip doesn't stretch the runway
+    pub fn max_gas_price(&self) -> Option<Nat> {
+        self.gas_prices.iter().max().cloned()
+    }
+}
+
+/// chain id => gas prices seen by the publisher
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct GasPriceHistory(pub HashMap<Nat, ChainGasPrices>);
+
+impl GasPriceHistory {
+    pub fn get(chain_id: &Nat) -> Option<ChainGasPrices> {
+        STATE.with(|state| state.borrow().gas_price_history.0.get(chain_id).cloned())
+    }
+
+    pub fn record(
+        chain_id: &Nat,
+        gas_price: &Nat,
+        fee: &Nat,
+        token_rates: &HashMap<String, TokenRate>,
+    ) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let history = state
+                .gas_price_history
+                .0
+                .entry(chain_id.clone())
+                .or_default();
+
+            history.gas_prices.push(gas_price.clone());
+            if history.gas_prices.len() > GAS_PRICE_HISTORY_SIZE {
+                let overflow = history.gas_prices.len() - GAS_PRICE_HISTORY_SIZE;
+                history.gas_prices.drain(..overflow);
+            }
+            history.fee = fee.clone();
+            history.token_rates = token_rates.clone();
+        })
+    }
+}
+
+impl RunwayParams {
+    pub fn execution_cost(&self, sub: &Subscription) -> Nat {
+        sub.method.gas_limit.clone() * self.gas_price.clone() + self.fee.clone()
+    }
+
+    /// Price mutation subscriptions can be executed on every timer tick, so it's the worst case
+    pub fn interval(&self, sub: &Subscription) -> Nat {
+        match &sub.method.exec_condition {
+            Some(ExecutionCondition::Frequency(frequency)) => frequency.clone(),
+            _ => self.timer_frequency.clone(),
+        }
+    }
+}
+
+/// Estimate the runway of the user's active subscriptions, that share the same `balance`,
+/// until the balance drops below the chain's min balance
+pub fn estimate(
+    subs: &[&Subscription],
+    balance: &Nat,
+    params: &RunwayParams,
+) -> Vec<SubscriptionRunway> {
+    let spendable = nat::saturating_sub(balance, &params.min_balance);
+
+    // the balance spent per second by all the active subscriptions
+    let spend_rate =
+        subs.iter()
+            .filter(|sub| sub.status.is_active)
+            .fold(Nat::from(0), |sum, sub| {
+                sum + params.execution_cost(sub) / nat::max(&params.interval(sub), &Nat::from(1))
+            });
+
+    #[allow(clippy::cmp_owned)]
+    let seconds_until_empty = if spend_rate == Nat::from(0) {
+        None
+    } else {
+        Some(spendable / spend_rate)
+    };
+
+    subs.iter()
+        .map(|sub| {
+            let interval = params.interval(sub);
+            let remaining_executions = seconds_until_empty
+                .as_ref()
+                .filter(|_| sub.status.is_active)
+                .map(|seconds| seconds.clone() / nat::max(&interval, &Nat::from(1)));
+            let is_at_risk = sub.status.is_active
+                && seconds_until_empty
+                    .as_ref()
+                    .is_some_and(|seconds| *seconds < params.warning_threshold);
+
+            SubscriptionRunway {
+                subscription_id: sub.id.clone(),
+                execution_cost: params.execution_cost(sub),
+                interval,
+                remaining_executions,
+                seconds_until_empty: seconds_until_empty.clone().filter(|_| sub.status.is_active),
+                is_at_risk,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{log, utils::time, PythiaError, STATE};
+
+use super::logger::SERVICE_ACCOUNTS;
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct ServiceAccount {
+    pub principal: Principal,
+    /// EVM address the principal acts for
+    pub owner: String,
+    pub added_by: Principal,
+    pub added_at: Nat,
+}
+
+/// principal => service account
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct ServiceAccounts(pub HashMap<Principal, ServiceAccount>);
+
+impl ServiceAccounts {
+    pub fn add(principal: Principal, owner: &str, added_by: Principal) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state.service_accounts.0.contains_key(&principal) {
+                return Err(PythiaError::ServiceAccountAlreadyExists.into());
+            }
+
+            state.service_accounts.0.insert(
+                principal,
+                ServiceAccount {
+                    principal,
+                    owner: owner.to_string(),
+                    added_by,
+                    added_at: Nat::from(time::in_seconds()),
+                },
+            );
+
+            log!("[{SERVICE_ACCOUNTS}] {added_by} added the service account {principal} acting for {owner}");
+            Ok(())
+        })
+    }
+
+    pub fn remove(principal: &Principal, removed_by: Principal) -> Result<()> {
+        STATE.with(|state| {
+            let account = state
+                .borrow_mut()
+                .service_accounts
+                .0
+                .remove(principal)
+                .ok_or(PythiaError::ServiceAccountDoesNotExist)?;
+
+            log!(
+                "[{SERVICE_ACCOUNTS}] {removed_by} removed the service account {principal} acting for {}",
+                account.owner
+            );
+            Ok(())
+        })
+    }
+
+    pub fn get_all() -> Vec<ServiceAccount> {
+        STATE.with(|state| {
+            let mut accounts: Vec<ServiceAccount> = state
+                .borrow()
+                .service_accounts
+                .0
+                .values()
+                .cloned()
+                .collect();
+            accounts.sort_by(|l, r| l.added_at.cmp(&r.added_at));
+            accounts
+        })
+    }
+
+    /// Returns the EVM address the principal is allowed to act for.
+    pub fn get_owner(principal: &Principal) -> Option<String> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .service_accounts
+                .0
+                .get(principal)
+                .map(|account| account.owner.clone())
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    log,
+    utils::{siwe, time},
+    PythiaError, STATE,
+};
+
+use super::{logger::SESSIONS, siwe::SiweAction};
+
+/// Maximum lifetime of a session in seconds
+pub const SESSION_MAX_DURATION: u64 = 7 * 24 * 60 * 60;
+
+/// A group of actions a session is allowed to perform. The login message
+/// grants a permission by listing its resource URI in the `Resources` section.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum SessionPermission {
+    Subscriptions,
+    Deposit,
+    Withdraw,
+}
+
+impl SessionPermission {
+    pub fn resource(&self) -> &'static str {
+        match self {
+            SessionPermission::Subscriptions => "urn:pythia:session:subscriptions",
+            SessionPermission::Deposit => "urn:pythia:session:deposit",
+            SessionPermission::Withdraw => "urn:pythia:session:withdraw",
+        }
+    }
+
+    pub fn from_resource(resource: &str) -> Option<Self> {
+        [
+            SessionPermission::Subscriptions,
+            SessionPermission::Deposit,
+            SessionPermission::Withdraw,
+        ]
+        .into_iter()
+        .find(|permission| permission.resource() == resource)
+    }
+
+    pub fn for_action(action: SiweAction) -> Option<Self> {
+        match action {
+            SiweAction::Subscribe
+            | SiweAction::UpdateSubscription
+            | SiweAction::StopSubscription
+            | SiweAction::StartSubscription => Some(SessionPermission::Subscriptions),
+            SiweAction::Deposit => Some(SessionPermission::Deposit),
+            SiweAction::Withdraw | SiweAction::WithdrawToken | SiweAction::CancelWithdrawal => {
+                Some(SessionPermission::Withdraw)
+            }
+            SiweAction::Login | SiweAction::ManageOperators | SiweAction::RequestAccess => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Serialize, Deserialize)]
+pub struct Session {
+    pub principal: Principal,
+    pub address: String,
+    pub permissions: Vec<SessionPermission>,
+    pub created_at: Nat,
+    pub expires_at: Nat,
+}
+
+impl Session {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Nat::from(time::in_seconds())
+    }
+}
+
+/// principal => session delegated to the principal
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct Sessions(pub HashMap<Principal, Session>);
+
+impl Sessions {
+    pub fn create(
+        principal: Principal,
+        address: &str,
+        permissions: Vec<SessionPermission>,
+        duration: u64,
+    ) -> Result<Session> {
+        if principal == Principal::anonymous() {
+            return Err(PythiaError::AnonymousPrincipal.into());
+        }
+        if permissions.is_empty() {
+            return Err(PythiaError::SessionWithoutPermissions.into());
+        }
+        if duration == 0 || duration > SESSION_MAX_DURATION {
+            return Err(PythiaError::InvalidSessionDuration.into());
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.sessions.0.retain(|_, session| !session.is_expired());
+
+            let now = time::in_seconds();
+            let session = Session {
+                principal,
+                address: address.to_string(),
+                permissions,
+                created_at: Nat::from(now),
+                expires_at: Nat::from(now + duration),
+            };
+            state.sessions.0.insert(principal, session.clone());
+
+            log!(
+                "[{SESSIONS}] Session created: principal = {principal}, address = {address}, expires_at = {}",
+                session.expires_at
+            );
+            Ok(session)
+        })
+    }
+
+    pub fn get(principal: &Principal) -> Result<Session> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let session = state
+                .sessions
+                .0
+                .get(principal)
+                .context(PythiaError::SessionDoesNotExist)?;
+
+            if session.is_expired() {
+                return Err(PythiaError::SessionIsExpired.into());
+            }
+
+            Ok(session.clone())
+        })
+    }
+
+    pub fn get_by_address(address: &str) -> Vec<Session> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .sessions
+                .0
+                .values()
+                .filter(|session| session.address == address && !session.is_expired())
+                .cloned()
+                .collect()
+        })
+    }
+
+    pub fn remove(principal: &Principal) {
+        STATE.with(|state| {
+            state.borrow_mut().sessions.0.remove(principal);
+            log!("[{SESSIONS}] Session removed: principal = {principal}");
+        })
+    }
+
+    /// Returns the address the principal acts for if its session has the permission.
+    pub fn authorize(principal: &Principal, permission: SessionPermission) -> Result<String> {
+        let session = Self::get(principal)?;
+        if !session.permissions.contains(&permission) {
+            return Err(PythiaError::SessionPermissionDenied(format!("{permission:?}")).into());
+        }
+
+        Ok(session.address)
+    }
+}
+
+/// The way an endpoint call proves which address it acts for
+pub enum Authorization {
+    /// SIWE message and signature passed with the call
+    Siwe { msg: String, sig: String },
+    /// Active session of the caller's principal
+    Session,
+}
+
+impl Authorization {
+    pub async fn address(&self, action: SiweAction, chain_id: &Nat) -> Result<String> {
+        match self {
+            Authorization::Siwe { msg, sig } => siwe::siwe_authorize(msg, sig, action, chain_id)
+                .await
+                .map_err(|e| PythiaError::from(e).into()),
+            Authorization::Session => {
+                let permission = SessionPermission::for_action(action)
+                    .context(PythiaError::SessionPermissionDenied(format!("{action:?}")))?;
+                Sessions::authorize(&ic_cdk::caller(), permission)
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{log, utils::time, STATE};
+
+use super::logger::CONTROLLERS;
+
+/// Seconds during which an issued nonce can be used in a SIWE message
+pub const SIWE_NONCE_TTL: u64 = 10 * 60;
+/// Maximum number of unconsumed nonces a principal can have at the same time
+pub const SIWE_MAX_PENDING_NONCES_PER_PRINCIPAL: usize = 5;
+
+#[derive(Error, Debug)]
+pub enum SiweError {
+    #[error("malformed message: {0}")]
+    MalformedMessage(String),
+    #[error("signature is not a valid hex: {0}")]
+    InvalidHex(String),
+    #[error("invalid signature length: expected 65 bytes, got {0}")]
+    InvalidSignatureLength(usize),
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("signature doesn't match the message address")]
+    SignatureMismatch,
+    #[error("contract wallet rejected the signature")]
+    ContractSignatureRejected,
+    #[error("failed to check the contract wallet signature: {0}")]
+    ContractCall(String),
+    #[error("message is expired")]
+    Expired,
+    #[error("message is not yet valid")]
+    NotYetValid,
+    #[error("domain is not allowed: {0}")]
+    DomainNotAllowed(String),
+    #[error("uri is not allowed: {0}")]
+    UriNotAllowed(String),
+    #[error("chain id mismatch: expected {expected}, message has {actual}")]
+    ChainIdMismatch { expected: String, actual: String },
+    #[error("message doesn't authorize the action {0}")]
+    ActionNotAuthorized(String),
+    #[error("nonce is not issued")]
+    NonceNotIssued,
+    #[error("nonce is already consumed")]
+    NonceAlreadyConsumed,
+    #[error("nonce is expired")]
+    NonceExpired,
+    #[error("nonce is issued to another caller")]
+    NonceIssuedToAnotherCaller,
+    #[error("too many pending nonces")]
+    TooManyPendingNonces,
+}
+
+/// Domains and URIs a SIWE message is allowed to be issued for.
+/// An empty list means that the corresponding field is not checked.
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct SiweConfig {
+    pub allowed_domains: Vec<String>,
+    pub allowed_uris: Vec<String>,
+}
+
+impl SiweConfig {
+    pub fn set_allowed_domains(domains: Vec<String>) {
+        STATE.with(|state| {
+            state.borrow_mut().siwe_config.allowed_domains = domains.clone();
+            log!("[{CONTROLLERS}] SIWE allowed domains updated: {domains:?}");
+        })
+    }
+
+    pub fn set_allowed_uris(uris: Vec<String>) {
+        STATE.with(|state| {
+            state.borrow_mut().siwe_config.allowed_uris = uris.clone();
+            log!("[{CONTROLLERS}] SIWE allowed uris updated: {uris:?}");
+        })
+    }
+
+    pub fn is_domain_allowed(domain: &str) -> bool {
+        STATE.with(|state| {
+            let allowed = &state.borrow().siwe_config.allowed_domains;
+            allowed.is_empty() || allowed.iter().any(|d| d == domain)
+        })
+    }
+
+    pub fn is_uri_allowed(uri: &str) -> bool {
+        STATE.with(|state| {
+            let allowed = &state.borrow().siwe_config.allowed_uris;
+            allowed.is_empty() || allowed.iter().any(|u| u == uri)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct SiweNonce {
+    pub expires_at: Nat,
+    pub consumed_at: Option<Nat>,
+    /// Only this principal can use the nonce, nonces issued before the binding have none and can't be used
+    pub issued_to: Option<Principal>,
+}
+
+impl SiweNonce {
+    fn validate(&self, principal: Principal, now: u64) -> Result<(), SiweError> {
+        if self.issued_to != Some(principal) {
+            return Err(SiweError::NonceIssuedToAnotherCaller);
+        }
+        if self.consumed_at.is_some() {
+            return Err(SiweError::NonceAlreadyConsumed);
+        }
+        if self.expires_at <= Nat::from(now) {
+            return Err(SiweError::NonceExpired);
+        }
+
+        Ok(())
+    }
+}
+
+/// nonce => its expiration and consumption time and the principal it's issued to
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct SiweNonces(pub HashMap<String, SiweNonce>);
+
+impl SiweNonces {
+    /// Issues the nonce to the principal, the number of unconsumed nonces per principal is limited
+    pub fn issue(nonce: &str, principal: Principal) -> Result<(), SiweError> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let now = time::in_seconds();
+
+            state
+                .siwe_nonces
+                .0
+                .retain(|_, n| n.expires_at > Nat::from(now));
+
+            let pending = state
+                .siwe_nonces
+                .0
+                .values()
+                .filter(|n| n.issued_to == Some(principal) && n.consumed_at.is_none())
+                .count();
+            if pending >= SIWE_MAX_PENDING_NONCES_PER_PRINCIPAL {
+                return Err(SiweError::TooManyPendingNonces);
+            }
+
+            state.siwe_nonces.0.insert(
+                nonce.to_string(),
+                SiweNonce {
+                    expires_at: Nat::from(now + SIWE_NONCE_TTL),
+                    consumed_at: None,
+                    issued_to: Some(principal),
+                },
+            );
+
+            Ok(())
+        })
+    }
+
+    /// Checks that the nonce is issued to the principal and can still be used, doesn't consume it.
+    pub fn check(nonce: &str, principal: Principal) -> Result<(), SiweError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let entry = state
+                .siwe_nonces
+                .0
+                .get(nonce)
+                .ok_or(SiweError::NonceNotIssued)?;
+
+            entry.validate(principal, time::in_seconds())
+        })
+    }
+
+    /// Marks the nonce as consumed, so a message carrying it can't be replayed.
+    /// Only the principal the nonce is issued to can consume it.
+    pub fn consume(nonce: &str, principal: Principal) -> Result<(), SiweError> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let now = time::in_seconds();
+
+            let entry = state
+                .siwe_nonces
+                .0
+                .get_mut(nonce)
+                .ok_or(SiweError::NonceNotIssued)?;
+
+            entry.validate(principal, now)?;
+            entry.consumed_at = Some(Nat::from(now));
+
+            Ok(())
+        })
+    }
+}
+
+/// An action a SIWE message is allowed to authorize. The message must list
+/// the action's resource URI in its `Resources` section.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SiweAction {
+    Subscribe,
+    UpdateSubscription,
+    StopSubscription,
+    StartSubscription,
+    Deposit,
+    Withdraw,
+    WithdrawToken,
+    CancelWithdrawal,
+    Login,
+    ManageOperators,
+    RequestAccess,
+}
+
+impl SiweAction {
+    pub fn resource(&self) -> &'static str {
+        match self {
+            SiweAction::Subscribe => "urn:pythia:subscribe",
+            SiweAction::UpdateSubscription => "urn:pythia:update_subscription",
+            SiweAction::StopSubscription => "urn:pythia:stop_subscription",
+            SiweAction::StartSubscription => "urn:pythia:start_subscription",
+            SiweAction::Deposit => "urn:pythia:deposit",
+            SiweAction::Withdraw => "urn:pythia:withdraw",
+            SiweAction::WithdrawToken => "urn:pythia:withdraw_token",
+            SiweAction::CancelWithdrawal => "urn:pythia:cancel_withdrawal",
+            SiweAction::Login => "urn:pythia:login",
+            SiweAction::ManageOperators => "urn:pythia:manage_operators",
+            SiweAction::RequestAccess => "urn:pythia:request_access",
+        }
+    }
+}
@@ -2,12 +2,21 @@ use candid::{CandidType, Nat, Principal};
 use serde::{Deserialize, Serialize};
 
 use super::{
+    access_requests::AccessRequests,
     balance::Balances,
     chains::Chains,
+    deposit_addresses::{DepositAddresses, Sweeps},
+    ledger::Ledger,
+    operators::Operators,
+    roles::Roles,
+    runway::GasPriceHistory,
+    service_accounts::ServiceAccounts,
+    session::Sessions,
+    siwe::{SiweConfig, SiweNonces},
     subscription::{Subscriptions, SubscriptionsIndexer},
     timer::Timer,
-    whitelist::Whitelist,
-    withdraw::WithdrawRequests,
+    whitelist::{Whitelist, WhitelistTiers},
+    withdraw::{WithdrawRequests, WithdrawRequestsIndexer},
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize, CandidType, Default)]
@@ -31,5 +40,20 @@ pub struct State {
     #[deprecated]
     pub is_timer_active: bool,
     pub timer: Option<Timer>,
-    pub whitelist: Whitelist,
+    pub deposit_addresses: DepositAddresses,
+    pub ledger: Ledger,
+    /// Seconds of runway below which subscriptions are marked as at risk
+    pub runway_warning_threshold: Nat,
+    pub withdraw_requests_indexer: WithdrawRequestsIndexer,
+    pub siwe_nonces: SiweNonces,
+    pub siwe_config: SiweConfig,
+    pub sessions: Sessions,
+    pub service_accounts: ServiceAccounts,
+    pub roles: Roles,
+    pub operators: Operators,
+    pub whitelist_tiers: WhitelistTiers,
+    pub whitelist_entries: Whitelist,
+    pub access_requests: AccessRequests,
+    pub sweeps: Sweeps,
+    pub gas_price_history: GasPriceHistory,
 }
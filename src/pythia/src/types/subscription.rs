@@ -9,13 +9,16 @@ use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
 use super::{
+    chains::Chains,
     errors::PythiaError,
     logger::{PUBLISHER, SUBSCRIPTION},
     methods::{ExecutionCondition, Method, MethodType, PriceMutationType},
+    operators::Actor,
+    runway::{self, RunwayParams},
 };
 use crate::{
     clone_with_state, log, metrics,
-    utils::{abi, address, canister, nat, sybil, validator, web3},
+    utils::{abi, address, canister, erc20, nat, sybil, validator, web3},
     STATE,
 };
 
@@ -42,6 +45,8 @@ pub struct Subscription {
     pub contract_addr: String,
     pub method: Method,
     pub status: SubscriptionStatus,
+    /// The subscription is deferred while the network's gas price is above it
+    pub max_gas_price: Option<Nat>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, CandidType, Default)]
@@ -50,6 +55,10 @@ pub struct SubscriptionStatus {
     pub last_update: Nat,
     pub executions_counter: Nat,
     pub failures_counter: Option<Nat>,
+    /// The balance is projected to run out within the runway warning threshold
+    pub is_at_risk: Option<bool>,
+    /// Why the last execution was deferred, cleared after the next execution
+    pub deferred_reason: Option<String>,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
@@ -70,6 +79,7 @@ pub struct SubsribeRequest {
     pub label: String,
     pub frequency_condition: Option<Nat>,
     pub price_mutation_condition: Option<PriceMutationCondition>,
+    pub max_gas_price: Option<Nat>,
     pub msg: String,
     pub sig: String,
 }
@@ -85,6 +95,9 @@ pub struct UpdateSubscriptionRequest {
     pub gas_limit: Option<Nat>,
     pub frequency_condition: Option<Nat>,
     pub price_mutation_condition: Option<PriceMutationCondition>,
+    pub max_gas_price: Option<Nat>,
+    /// Remove the subscription's max gas price, `max_gas_price` is ignored
+    pub clear_max_gas_price: Option<bool>,
     pub msg: String,
     pub sig: String,
 }
@@ -118,6 +131,9 @@ impl Subscriptions {
         }?;
 
         exec_contidion.validate().await?;
+        if let ExecutionCondition::Frequency(frequency) = &exec_contidion {
+            validator::tier_frequency(owner, frequency)?;
+        }
         let (abi, method_type) =
             abi::resolve_abi(req.method_abi.clone(), req.feed_id.clone(), req.is_random)?;
         if let Some(feed_id) = req.feed_id.clone() {
@@ -148,6 +164,7 @@ impl Subscriptions {
                 is_active: true,
                 ..Default::default()
             },
+            max_gas_price: req.max_gas_price.clone(),
         };
 
         STATE.with(|state| {
@@ -327,17 +344,19 @@ impl Subscriptions {
         })
     }
 
-    pub fn stop(chain_id: &Nat, owner: &str, id: &Nat) -> Result<()> {
+    pub fn stop(chain_id: &Nat, actor: &Actor, id: &Nat) -> Result<()> {
         let id = id.clone();
         STATE.with(|state| {
             let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let operators = &state.operators;
             let subscription = state
                 .subscriptions
                 .0
                 .get_mut(chain_id)
                 .context(PythiaError::ChainDoesNotExistInSubscriptions)?
                 .iter_mut()
-                .find(|s| s.id == id && s.owner == owner)
+                .find(|s| s.id == id && operators.can_manage(&s.owner, actor, chain_id, &s.id))
                 .context(PythiaError::SubscriptionDoesNotExist)?;
 
             let subscription_status = &mut subscription.status;
@@ -358,9 +377,15 @@ impl Subscriptions {
         })
     }
 
-    pub fn stop_all(chain_id: Option<Nat>, ids: Vec<Nat>, owner: Option<String>) -> Result<()> {
+    /// Returns ids of the stopped subscriptions
+    pub fn stop_all(
+        chain_id: Option<Nat>,
+        ids: Vec<Nat>,
+        owner: Option<String>,
+    ) -> Result<Vec<Nat>> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
+            let mut stopped = vec![];
             state
                 .subscriptions
                 .0
@@ -382,6 +407,7 @@ impl Subscriptions {
                         if sub.status.is_active == true && (ids.is_empty() || ids.contains(&sub.id))
                         {
                             sub.status.is_active = false;
+                            stopped.push(sub.id.clone());
                             metrics!(dec ACTIVE_SUBSCRIPTIONS, _chain_id);
                         }
                     });
@@ -389,21 +415,27 @@ impl Subscriptions {
 
             log!("[{SUBSCRIPTION}] Subscription are stopped");
 
-            Ok(())
+            Ok(stopped)
         })
     }
 
-    pub fn start(chain_id: &Nat, owner: &str, id: &Nat) -> Result<()> {
+    pub fn start(chain_id: &Nat, actor: &Actor, id: &Nat) -> Result<()> {
+        if Chains::is_decommissioning(chain_id) {
+            return Err(PythiaError::ChainIsDecommissioning.into());
+        }
+
         let id = id.clone();
         STATE.with(|state| {
             let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let operators = &state.operators;
             let subscription = state
                 .subscriptions
                 .0
                 .get_mut(chain_id)
                 .context(PythiaError::ChainDoesNotExistInSubscriptions)?
                 .iter_mut()
-                .find(|s| s.id == id && s.owner == owner)
+                .find(|s| s.id == id && operators.can_manage(&s.owner, actor, chain_id, &s.id))
                 .context(PythiaError::SubscriptionDoesNotExist)?;
 
             let subscription_status = &mut subscription.status;
@@ -461,7 +493,7 @@ impl Subscriptions {
         })
     }
 
-    pub async fn update(req: &UpdateSubscriptionRequest, address: &str) -> Result<()> {
+    pub async fn update(req: &UpdateSubscriptionRequest, actor: &Actor) -> Result<()> {
         let state_timer_frequency = clone_with_state!(timer_frequency);
         let exec_condition = match (
             req.frequency_condition.clone(),
@@ -470,6 +502,7 @@ impl Subscriptions {
             (Some(frequency), Some(_)) | (Some(frequency), None) => {
                 validator::subscription_frequency(frequency.clone(), state_timer_frequency)
                     .context(PythiaError::InvalidSubscriptionFrequency)?;
+                validator::tier_frequency(&Self::get(&req.chain_id, &req.id)?.owner, &frequency)?;
                 Some(ExecutionCondition::Frequency(frequency))
             }
             (None, Some(price_mutation_condition_req)) => {
@@ -489,13 +522,18 @@ impl Subscriptions {
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
+            let state = &mut *state;
+            let operators = &state.operators;
             let subscription = state
                 .subscriptions
                 .0
                 .get_mut(&req.chain_id)
                 .context(PythiaError::ChainDoesNotExistInSubscriptions)?
                 .iter_mut()
-                .find(|sub| sub.id == req.id && sub.owner == address)
+                .find(|sub| {
+                    sub.id == req.id
+                        && operators.can_manage(&sub.owner, actor, &req.chain_id, &sub.id)
+                })
                 .context(PythiaError::SubscriptionDoesNotExist)?;
 
             subscription.method.exec_condition = exec_condition;
@@ -504,6 +542,12 @@ impl Subscriptions {
                 subscription.method.gas_limit = gas_limit;
             }
 
+            if req.clear_max_gas_price.unwrap_or_default() {
+                subscription.max_gas_price = None;
+            } else if let Some(max_gas_price) = req.max_gas_price.clone() {
+                subscription.max_gas_price = Some(max_gas_price);
+            }
+
             if let Some(contract_addr) = req.contract_addr.clone() {
                 subscription.contract_addr = address::normalize(&contract_addr)
                     .context(PythiaError::InvalidAddressFormat)?;
@@ -527,9 +571,17 @@ impl Subscriptions {
         })
     }
 
-    pub fn check_limits(owner: &str) -> Result<()> {
+    /// The whitelist tier of the owner replaces the wallet limit and restricts the chains
+    pub fn check_limits(owner: &str, chain_id: &Nat) -> Result<()> {
         STATE.with(|state| {
             let state = state.borrow();
+            let tier = state.whitelist_tiers.of(&state.whitelist_entries, owner);
+
+            if let Some(tier) = tier {
+                if !tier.is_chain_allowed(chain_id) {
+                    return Err(PythiaError::ChainIsNotAllowedByTier.into());
+                }
+            }
 
             let owners = state
                 .subscriptions
@@ -546,11 +598,15 @@ impl Subscriptions {
                 return Err(PythiaError::TotalSubscriptionsLimitReached.into());
             }
 
-            if owners
-                .iter()
-                .filter(|&_owner| _owner.clone() == owner)
-                .count() as u64
-                > state.subs_limit_wallet
+            let subs_limit_wallet = tier
+                .map(|tier| tier.subs_limit.clone())
+                .unwrap_or(state.subs_limit_wallet.clone());
+            if Nat::from(
+                owners
+                    .iter()
+                    .filter(|&_owner| _owner.clone() == owner)
+                    .count(),
+            ) > subs_limit_wallet
             {
                 return Err(PythiaError::WalletSubscriptionsLimitReached.into());
             }
@@ -598,10 +654,21 @@ impl Subscriptions {
             .collect::<Result<Vec<Nat>>>()
             .context("failed to get fees")?;
 
+        let futures = chains_to_check.iter().map(erc20::rates).collect::<Vec<_>>();
+        let token_rates = join_all(futures)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .context("failed to get token rates")?;
+
         STATE.with(|state| {
             let mut state = state.borrow_mut();
             let balances = state.balances.0.clone();
             let chains = state.chains.0.clone();
+            let whitelist = state.whitelist_entries.clone();
+            let whitelist_tiers = state.whitelist_tiers.clone();
+            let timer_frequency = state.timer_frequency.clone();
+            let warning_threshold = state.runway_warning_threshold.clone();
             let subscriptions = &mut state.subscriptions.0;
 
             let mut i = 0;
@@ -612,6 +679,7 @@ impl Subscriptions {
 
                 let gas_price = gas_prices.get(i).context("gas price not found")?;
                 let fee = fees.get(i).context("fee not found")?;
+                let rates = token_rates.get(i).context("token rates not found")?;
                 let chain_min_balance = &chains
                     .get(chain_id)
                     .context(PythiaError::ChainDoesNotExistInSubscriptions)?
@@ -624,15 +692,19 @@ impl Subscriptions {
                         .context(PythiaError::ChainDoesNotExistInSubscriptions)?
                         .get(&owner)
                         .context(PythiaError::UnableToGetBalance)?;
+                    let fee = whitelist_tiers.fee(&whitelist, &owner, fee);
 
                     let mut need_funds = subs.iter().fold(Nat::from(0), |res, sub| {
                         res + (sub.method.gas_limit.clone() * gas_price.clone()) + fee.clone()
                     });
                     need_funds += chain_min_balance.clone();
 
-                    if balance.amount < need_funds {
-                        subs.into_iter()
-                            .for_each(|sub| sub.status.is_active = false);
+                    let balance = balance.value_in_native(rates);
+                    if balance < need_funds {
+                        subs.into_iter().for_each(|sub| {
+                            sub.status.is_active = false;
+                            sub.status.is_at_risk = Some(false);
+                        });
 
 
                         metrics!(dec ACTIVE_SUBSCRIPTIONS, chain_id);
@@ -641,6 +713,31 @@ impl Subscriptions {
                             owner,
                             chain_id
                         );
+                        continue;
+                    }
+
+                    let params = RunwayParams {
+                        gas_price: gas_price.clone(),
+                        fee: fee.clone(),
+                        min_balance: chain_min_balance.clone(),
+                        timer_frequency: timer_frequency.clone(),
+                        warning_threshold: warning_threshold.clone(),
+                    };
+                    let runways = runway::estimate(
+                        &subs.iter().map(|sub| &**sub).collect::<Vec<_>>(),
+                        &balance,
+                        &params,
+                    );
+                    for (sub, runway) in subs.into_iter().zip(runways) {
+                        if runway.is_at_risk && sub.status.is_at_risk != Some(true) {
+                            log!(
+                                "[{SUBSCRIPTION}] Subscription is at risk of running out of balance: id = {}, owner = {}, chain_id = {}",
+                                sub.id,
+                                owner,
+                                chain_id
+                            );
+                        }
+                        sub.status.is_at_risk = Some(runway.is_at_risk);
                     }
                 }
                 i += 1;
@@ -664,6 +761,7 @@ impl Subscriptions {
 
             subscription.status.last_update = Nat::from(last_update);
             subscription.status.executions_counter += 1;
+            subscription.status.deferred_reason = None;
             if is_failed {
                 if let Some(failures_counter) = subscription.status.failures_counter.as_mut() {
                     *failures_counter += 1;
@@ -679,17 +777,48 @@ impl Subscriptions {
         })
     }
 
+    /// Skip the execution of the subscription without counting it as a failure, the execution condition
+    /// is restored to `exec_condition`, so the subscription is published as soon as it's possible
+    pub fn defer(
+        chain_id: &Nat,
+        sub_id: &Nat,
+        exec_condition: Option<ExecutionCondition>,
+        reason: String,
+    ) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let subscription = state
+                .subscriptions
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInSubscriptions)?
+                .iter_mut()
+                .find(|s| s.id == *sub_id)
+                .context(PythiaError::SubscriptionDoesNotExist)?;
+
+            subscription.method.exec_condition = exec_condition;
+            subscription.status.deferred_reason = Some(reason);
+
+            Ok(())
+        })
+    }
+
     pub async fn get_publishable() -> (Vec<(Nat, Vec<Subscription>)>, bool) {
         let mut is_active = false;
         let mut publishable_subs = vec![];
         for (chain_id, subscriptions) in STATE.with(|s| s.borrow().subscriptions.0.clone()) {
+            let is_chain_paused = Chains::is_paused(&chain_id);
             let mut publishable_subs_for_chain = vec![];
             for subscription in subscriptions {
                 if !subscription.status.is_active {
                     continue;
                 }
 
+                // the timer keeps running for the subscriptions of the paused chain to probe it
                 is_active = true;
+                if is_chain_paused {
+                    continue;
+                }
 
                 let Some(mut exec_condition) = subscription.method.exec_condition.clone() else {
                     continue;
@@ -1,73 +1,331 @@
-use candid::CandidType;
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use candid::{CandidType, Nat, Principal};
 use serde::{Deserialize, Serialize};
 
-use crate::{log, STATE};
+use crate::{log, utils::time, PythiaError, STATE};
 
 use super::logger::WHITELIST;
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+#[derive(Clone, Debug, Serialize, Deserialize, CandidType)]
 pub struct WhitelistEntry {
     pub address: String,
     pub is_blacklisted: bool,
+    /// Name of the tier, `None` means the global limits
+    pub tier: Option<String>,
+    /// Principal that added the address
+    pub added_by: Principal,
+    pub added_at: Nat,
+    pub note: Option<String>,
+    /// The entry is removed and the subscriptions of the address are stopped after this time
+    pub expires_at: Option<Nat>,
+    pub blacklist_reason: Option<String>,
+}
+
+impl WhitelistEntry {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.as_ref().map_or(false, |expires_at| {
+            *expires_at <= Nat::from(time::in_seconds())
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct WhitelistEntryRequest {
+    pub address: String,
+    pub note: Option<String>,
+    pub expires_at: Option<Nat>,
+    pub tier: Option<String>,
+}
+
+/// normalized address => entry
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct Whitelist(pub HashMap<String, WhitelistEntry>);
+
+/// Limits applied to the whitelisted addresses of the tier instead of the global ones
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct WhitelistTier {
+    pub name: String,
+    /// Maximum number of subscriptions of an address
+    pub subs_limit: Nat,
+    /// Minimum frequency of a subscription in seconds
+    pub min_frequency: Nat,
+    /// Chains the subscriptions can be created on, empty means any chain
+    pub allowed_chains: Vec<Nat>,
+    /// Share of the platform fee in percent from 1 to 100, e.g. 80 is a 20% discount
+    pub fee_multiplier: Nat,
 }
 
-pub type Whitelist = Vec<WhitelistEntry>;
+impl WhitelistTier {
+    pub fn is_chain_allowed(&self, chain_id: &Nat) -> bool {
+        self.allowed_chains.is_empty() || self.allowed_chains.contains(chain_id)
+    }
+
+    pub fn apply_fee_multiplier(&self, fee: &Nat) -> Nat {
+        fee.clone() * self.fee_multiplier.clone() / Nat::from(100)
+    }
+}
+
+/// tier name => tier
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct WhitelistTiers(pub HashMap<String, WhitelistTier>);
+
+impl WhitelistTiers {
+    pub fn set(tier: WhitelistTier) -> Result<()> {
+        #[allow(clippy::cmp_owned)]
+        if tier.name.is_empty()
+            || tier.subs_limit == Nat::from(0)
+            || tier.min_frequency == Nat::from(0)
+            || tier.fee_multiplier == Nat::from(0)
+            || tier.fee_multiplier > Nat::from(100)
+        {
+            return Err(PythiaError::InvalidWhitelistTier.into());
+        }
+
+        STATE.with(|state| {
+            log!("[{WHITELIST}] Tier set: {tier:?}");
+            state
+                .borrow_mut()
+                .whitelist_tiers
+                .0
+                .insert(tier.name.clone(), tier);
+
+            Ok(())
+        })
+    }
+
+    pub fn remove(name: &str) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state
+                .whitelist_entries
+                .0
+                .values()
+                .any(|entry| entry.tier.as_deref() == Some(name))
+            {
+                return Err(PythiaError::WhitelistTierIsAssigned.into());
+            }
+
+            state
+                .whitelist_tiers
+                .0
+                .remove(name)
+                .context(PythiaError::WhitelistTierDoesNotExist)?;
+
+            log!("[{WHITELIST}] Tier removed: {name}");
+            Ok(())
+        })
+    }
+
+    pub fn get_all() -> Vec<WhitelistTier> {
+        STATE.with(|state| state.borrow().whitelist_tiers.0.values().cloned().collect())
+    }
+
+    /// Tier of the whitelisted address, `None` if it isn't assigned
+    pub fn of(&self, whitelist: &Whitelist, address: &str) -> Option<&WhitelistTier> {
+        whitelist
+            .0
+            .get(address)
+            .and_then(|entry| entry.tier.as_ref())
+            .and_then(|name| self.0.get(name))
+    }
+
+    /// Platform fee of the address with the multiplier of its tier
+    pub fn fee(&self, whitelist: &Whitelist, address: &str, fee: &Nat) -> Nat {
+        self.of(whitelist, address)
+            .map_or(fee.clone(), |tier| tier.apply_fee_multiplier(fee))
+    }
+}
 
 pub fn is_whitelisted(address: &str) -> bool {
     STATE.with(|state| {
         state
             .borrow()
-            .whitelist
-            .iter()
-            .any(|entry| entry.address == address && !entry.is_blacklisted)
+            .whitelist_entries
+            .0
+            .get(address)
+            .map_or(false, |entry| !entry.is_blacklisted && !entry.is_expired())
     })
 }
 
-pub fn add(address: &str) {
+pub fn is_blacklisted(address: &str) -> bool {
+    STATE.with(|state| {
+        state
+            .borrow()
+            .whitelist_entries
+            .0
+            .get(address)
+            .map_or(false, |entry| entry.is_blacklisted)
+    })
+}
+
+/// Add the addresses that aren't in the whitelist yet. The requests are validated
+/// as a whole, so nothing is added if any of them is invalid.
+///
+/// # Returns
+///
+/// Returns the added addresses
+pub fn add(reqs: &[WhitelistEntryRequest], added_by: Principal) -> Result<Vec<String>> {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        let entry = WhitelistEntry {
-            address: address.to_string(),
-            is_blacklisted: false,
-        };
-        state.whitelist.push(entry);
+        let now = Nat::from(time::in_seconds());
+
+        for req in reqs {
+            if let Some(expires_at) = &req.expires_at {
+                if *expires_at <= now {
+                    return Err(PythiaError::InvalidWhitelistExpiration.into());
+                }
+            }
+            if let Some(tier) = &req.tier {
+                if !state.whitelist_tiers.0.contains_key(tier) {
+                    return Err(PythiaError::WhitelistTierDoesNotExist.into());
+                }
+            }
+        }
+
+        let mut added = vec![];
+        for req in reqs {
+            if state.whitelist_entries.0.contains_key(&req.address) {
+                continue;
+            }
+
+            let entry = WhitelistEntry {
+                address: req.address.clone(),
+                is_blacklisted: false,
+                tier: req.tier.clone(),
+                added_by,
+                added_at: now.clone(),
+                note: req.note.clone(),
+                expires_at: req.expires_at.clone(),
+                blacklist_reason: None,
+            };
+            state.whitelist_entries.0.insert(req.address.clone(), entry);
+            added.push(req.address.clone());
+
+            log!(
+                "[{WHITELIST}] Address added: {}, added_by = {added_by}, expires_at = {:?}",
+                req.address,
+                req.expires_at
+            );
+        }
 
-        log!("[{WHITELIST}] Address added: {}", address);
+        Ok(added)
     })
 }
 
 pub fn remove(address: &str) {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        state.whitelist.retain(|entry| entry.address != address);
+        state.whitelist_entries.0.remove(address);
         log!("[{WHITELIST}] Address removed: {}", address);
     })
 }
 
-pub fn blacklist(address: &str) {
+pub fn blacklist(address: &str, reason: Option<String>) {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        state.whitelist.iter_mut().for_each(|entry| {
-            if entry.address == address {
-                entry.is_blacklisted = true;
-            }
-        });
-        log!("[{WHITELIST}] Address balcklisted: {}", address);
+        if let Some(entry) = state.whitelist_entries.0.get_mut(address) {
+            entry.is_blacklisted = true;
+            entry.blacklist_reason = reason.clone();
+        }
+        log!(
+            "[{WHITELIST}] Address balcklisted: {}, reason = {reason:?}",
+            address
+        );
     })
 }
 
 pub fn unblacklist(address: &str) {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        state.whitelist.iter_mut().for_each(|entry| {
-            if entry.address == address {
-                entry.is_blacklisted = false;
-            }
-        });
+        if let Some(entry) = state.whitelist_entries.0.get_mut(address) {
+            entry.is_blacklisted = false;
+            entry.blacklist_reason = None;
+        }
         log!("[{WHITELIST}] Address unbalcklisted: {}", address);
     })
 }
 
-pub fn get_list() -> Whitelist {
-    STATE.with(|state| state.borrow().whitelist.clone())
+/// Whitelist entries sorted by address
+pub fn get_list() -> Vec<WhitelistEntry> {
+    STATE.with(|state| {
+        let mut entries = state
+            .borrow()
+            .whitelist_entries
+            .0
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.sort_by(|l, r| l.address.cmp(&r.address));
+
+        entries
+    })
+}
+
+/// Remove the expired entries
+///
+/// # Returns
+///
+/// Returns the addresses of the removed entries
+pub fn remove_expired() -> Vec<String> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let expired = state
+            .whitelist_entries
+            .0
+            .values()
+            .filter(|entry| entry.is_expired())
+            .map(|entry| entry.address.clone())
+            .collect::<Vec<_>>();
+
+        for address in &expired {
+            state.whitelist_entries.0.remove(address);
+            log!("[{WHITELIST}] Address expired: {address}");
+        }
+
+        expired
+    })
+}
+
+pub fn set_tier(address: &str, tier: Option<String>) -> Result<()> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let state = &mut *state;
+        if let Some(tier) = &tier {
+            if !state.whitelist_tiers.0.contains_key(tier) {
+                return Err(PythiaError::WhitelistTierDoesNotExist.into());
+            }
+        }
+
+        let entry = state
+            .whitelist_entries
+            .0
+            .get_mut(address)
+            .context(PythiaError::UserIsNotWhitelisted)?;
+        entry.tier = tier.clone();
+
+        log!("[{WHITELIST}] Tier assigned: address = {address}, tier = {tier:?}");
+        Ok(())
+    })
+}
+
+pub fn get_tier(address: &str) -> Option<WhitelistTier> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .whitelist_tiers
+            .of(&state.whitelist_entries, address)
+            .cloned()
+    })
+}
+
+/// Platform fee of the address with the multiplier of its tier
+pub fn fee(address: &str, fee: &Nat) -> Nat {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .whitelist_tiers
+            .fee(&state.whitelist_entries, address, fee)
+    })
 }
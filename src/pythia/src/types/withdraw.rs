@@ -5,14 +5,94 @@ use anyhow::{Context, Result};
 use candid::{CandidType, Nat};
 use serde::{Deserialize, Serialize};
 
-use crate::{log, STATE};
+use crate::{
+    log,
+    utils::{nat, time},
+    STATE,
+};
 
 use super::{errors::PythiaError, logger::WITHDRAWER};
 
+/// Number of times the funds of a request are handed to a transfer before the request is abandoned
+pub const MAX_WITHDRAW_ATTEMPTS: u32 = 5;
+/// Time in seconds a confirmed or abandoned request is kept before it's pruned
+pub const WITHDRAW_REQUEST_RETENTION: u64 = 60 * 60 * 24 * 30;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, CandidType)]
+pub struct WithdrawRequestsIndexer(pub Nat);
+
+impl WithdrawRequestsIndexer {
+    pub fn new_index() -> Nat {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.withdraw_requests_indexer.0 += 1;
+            state.withdraw_requests_indexer.0.clone()
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Serialize, Deserialize)]
+pub enum WithdrawStatus {
+    #[default]
+    Queued,
+    /// Handed to the multitransfer, the request can't be cancelled anymore
+    Sending,
+    Sent {
+        tx_hash: String,
+        /// Nonce of the PMA used by the transaction
+        nonce: Option<Nat>,
+    },
+    Confirmed {
+        tx_hash: String,
+    },
+    /// The funds were not sent, the request is retried on the next run
+    Failed {
+        reason: String,
+    },
+    /// The funds were not sent after `MAX_WITHDRAW_ATTEMPTS`, the charged amount is returned to the owner's balance
+    Abandoned {
+        reason: String,
+    },
+}
+
+impl WithdrawStatus {
+    /// The funds of the request have not been sent yet
+    pub fn is_unsent(&self) -> bool {
+        matches!(self, WithdrawStatus::Queued | WithdrawStatus::Failed { .. })
+    }
+
+    /// The request won't change anymore
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            WithdrawStatus::Confirmed { .. } | WithdrawStatus::Abandoned { .. }
+        )
+    }
+}
+
 #[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
 pub struct WithdrawRequest {
+    pub id: Nat,
+    pub chain_id: Nat,
+    /// Address of the user whose balance is withdrawn
+    pub owner: String,
     pub amount: Nat,
     pub receiver: String,
+    /// ERC-20 token address, `None` for the native coin
+    pub token: Option<String>,
+    pub status: WithdrawStatus,
+    /// Amount deducted from the owner's balance, including the transfer gas
+    pub charged: Nat,
+    /// Share of the transfer gas paid by the request, `charged - amount`
+    pub fee: Nat,
+    /// Id of the first request of the withdrawal the request was made with
+    pub batch_id: Nat,
+    /// Subscriptions stopped by the withdrawal, kept by a single request of the batch
+    pub stopped_subscriptions: Vec<Nat>,
+    /// Number of times the funds were handed to a transfer
+    pub attempts: u32,
+    pub created_at: Nat,
+    pub updated_at: Nat,
 }
 
 /// chain id => withdraw requests
@@ -20,8 +100,17 @@ pub struct WithdrawRequest {
 pub struct WithdrawRequests(pub HashMap<Nat, Vec<WithdrawRequest>>);
 
 impl WithdrawRequests {
-    pub fn add(chain_id: &Nat, receiver: &str, amount: &Nat) -> Result<()> {
+    pub fn add(
+        chain_id: &Nat,
+        owner: &str,
+        receiver: &str,
+        amount: &Nat,
+        charged: &Nat,
+        token: Option<String>,
+    ) -> Result<Nat> {
+        let id = WithdrawRequestsIndexer::new_index();
         STATE.with(|state| {
+            let now = Nat::from(time::in_seconds());
             state
                 .borrow_mut()
                 .withdraw_requests
@@ -29,39 +118,305 @@ impl WithdrawRequests {
                 .get_mut(chain_id)
                 .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
                 .push(WithdrawRequest {
+                    id: id.clone(),
+                    chain_id: chain_id.clone(),
+                    owner: owner.to_string(),
                     amount: amount.clone(),
                     receiver: receiver.to_string(),
+                    token: token.clone(),
+                    status: WithdrawStatus::Queued,
+                    charged: charged.clone(),
+                    fee: nat::saturating_sub(charged, amount),
+                    batch_id: id.clone(),
+                    stopped_subscriptions: vec![],
+                    attempts: 0,
+                    created_at: now.clone(),
+                    updated_at: now,
                 });
 
             log!(
-                "[{WITHDRAWER}] Withdraw request added: chain_id = {}, amount = {}, receiver = {}",
+                "[{WITHDRAWER}] Withdraw request added: chain_id = {}, id = {}, amount = {}, receiver = {}, token = {:?}",
                 chain_id,
+                id,
                 amount,
-                receiver
+                receiver,
+                token
             );
 
+            Ok(id)
+        })
+    }
+
+    pub fn update_status(chain_id: &Nat, ids: &[Nat], status: WithdrawStatus) -> Result<()> {
+        STATE.with(|state| {
+            let now = Nat::from(time::in_seconds());
+            state
+                .borrow_mut()
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
+                .iter_mut()
+                .filter(|req| ids.contains(&req.id))
+                .for_each(|req| {
+                    req.status = status.clone();
+                    req.updated_at = now.clone();
+                });
+
+            log!(
+                "[{WITHDRAWER}] Withdraw requests updated: chain_id = {}, ids = {:?}, status = {:?}",
+                chain_id,
+                ids,
+                status
+            );
             Ok(())
         })
     }
 
-    pub fn erase(chain_id: &Nat) -> Result<()> {
+    /// Mark the requests that are still unsent as being sent and count the attempt, returns their ids
+    pub fn start_sending(chain_id: &Nat, ids: &[Nat]) -> Result<Vec<Nat>> {
         STATE.with(|state| {
+            let now = Nat::from(time::in_seconds());
+            Ok(state
+                .borrow_mut()
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
+                .iter_mut()
+                .filter(|req| ids.contains(&req.id) && req.status.is_unsent())
+                .map(|req| {
+                    req.status = WithdrawStatus::Sending;
+                    req.attempts += 1;
+                    req.updated_at = now.clone();
+                    req.id.clone()
+                })
+                .collect())
+        })
+    }
+
+    /// Set the share of the transfer gas paid by every request, id => fee,
+    /// the amount sent to the receiver is the rest of the charged amount
+    pub fn set_fees(chain_id: &Nat, fees: &HashMap<Nat, Nat>) -> Result<()> {
+        STATE.with(|state| {
+            let now = Nat::from(time::in_seconds());
             state
                 .borrow_mut()
                 .withdraw_requests
                 .0
                 .get_mut(chain_id)
                 .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
-                .clear();
+                .iter_mut()
+                .for_each(|req| {
+                    if let Some(fee) = fees.get(&req.id) {
+                        req.amount = nat::saturating_sub(&req.charged, fee);
+                        req.fee = fee.clone();
+                        req.updated_at = now.clone();
+                    }
+                });
+
+            Ok(())
+        })
+    }
+
+    /// Return the unspent part of the fee, the charged amount is reduced by the `refund`
+    pub fn refund(chain_id: &Nat, id: &Nat, refund: &Nat) -> Result<()> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let req = state
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
+                .iter_mut()
+                .find(|req| req.id == *id)
+                .context(PythiaError::WithdrawRequestDoesNotExist)?;
+
+            req.fee = nat::saturating_sub(&req.fee, refund);
+            req.charged = nat::saturating_sub(&req.charged, refund);
+            req.updated_at = Nat::from(time::in_seconds());
 
             log!(
-                "[{WITHDRAWER}] Withdraw request removed: chain_id = {}",
+                "[{WITHDRAWER}] Withdraw request refunded: chain_id = {}, id = {}, refund = {}",
                 chain_id,
+                id,
+                refund
             );
             Ok(())
         })
     }
 
+    /// Group the requests made with a single withdrawal into a batch, the subscriptions
+    /// stopped by the withdrawal are kept by the first request of the batch
+    pub fn set_stopped_subscriptions(chain_id: &Nat, ids: &[Nat], subs: &[Nat]) -> Result<()> {
+        let batch_id = ids
+            .first()
+            .context(PythiaError::WithdrawRequestDoesNotExist)?;
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
+                .iter_mut()
+                .filter(|req| ids.contains(&req.id))
+                .for_each(|req| {
+                    req.batch_id = batch_id.clone();
+                    if req.id == *batch_id {
+                        req.stopped_subscriptions = subs.to_vec();
+                    }
+                });
+
+            Ok(())
+        })
+    }
+
+    /// Remove the request of the `owner` that is not handed to the multitransfer yet.
+    /// Returns the request and the subscriptions stopped by its batch if no other request
+    /// of the batch is pending, otherwise they are kept by the rest of the batch
+    pub fn cancel(chain_id: &Nat, id: &Nat, owner: &str) -> Result<(WithdrawRequest, Vec<Nat>)> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let reqs = state
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?;
+
+            let index = reqs
+                .iter()
+                .position(|req| req.id == *id && req.owner == owner)
+                .context(PythiaError::WithdrawRequestDoesNotExist)?;
+            if !reqs[index].status.is_unsent() {
+                return Err(PythiaError::WithdrawRequestIsAlreadySent.into());
+            }
+
+            let mut req = reqs.remove(index);
+            let mut batch = reqs
+                .iter_mut()
+                .filter(|r| r.batch_id == req.batch_id)
+                .collect::<Vec<_>>();
+
+            let stopped = if batch.iter().any(|r| !r.status.is_final()) {
+                if let Some(holder) = batch.first_mut() {
+                    holder
+                        .stopped_subscriptions
+                        .append(&mut req.stopped_subscriptions);
+                }
+                vec![]
+            } else {
+                batch
+                    .iter_mut()
+                    .flat_map(|r| std::mem::take(&mut r.stopped_subscriptions))
+                    .chain(req.stopped_subscriptions.clone())
+                    .collect()
+            };
+
+            log!(
+                "[{WITHDRAWER}] Withdraw request cancelled: chain_id = {}, id = {}",
+                chain_id,
+                id
+            );
+            Ok((req, stopped))
+        })
+    }
+
+    /// Give up on the failed request, returns it so the charged amount can be returned to the owner
+    pub fn abandon(chain_id: &Nat, id: &Nat, reason: String) -> Result<WithdrawRequest> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let req = state
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?
+                .iter_mut()
+                .find(|req| req.id == *id)
+                .context(PythiaError::WithdrawRequestDoesNotExist)?;
+            if !matches!(req.status, WithdrawStatus::Failed { .. }) {
+                return Err(PythiaError::WithdrawRequestIsNotFailed.into());
+            }
+
+            req.status = WithdrawStatus::Abandoned { reason };
+            req.updated_at = Nat::from(time::in_seconds());
+
+            log!(
+                "[{WITHDRAWER}] Withdraw request abandoned: chain_id = {}, id = {}, attempts = {}",
+                chain_id,
+                id,
+                req.attempts
+            );
+            Ok(req.clone())
+        })
+    }
+
+    /// Remove the final requests that were last updated before `before`, returns the number of removed requests
+    pub fn prune(chain_id: &Nat, before: u64) -> Result<usize> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let reqs = state
+                .withdraw_requests
+                .0
+                .get_mut(chain_id)
+                .context(PythiaError::ChainDoesNotExistInWithdrawalRequests)?;
+
+            let len = reqs.len();
+            reqs.retain(|req| !req.status.is_final() || req.updated_at >= Nat::from(before));
+            let pruned = len - reqs.len();
+
+            if pruned > 0 {
+                log!(
+                    "[{WITHDRAWER}] Withdraw requests pruned: chain_id = {}, count = {}",
+                    chain_id,
+                    pruned
+                );
+            }
+            Ok(pruned)
+        })
+    }
+
+    pub fn get(id: &Nat) -> Result<WithdrawRequest> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .withdraw_requests
+                .0
+                .values()
+                .flatten()
+                .find(|req| req.id == *id)
+                .cloned()
+                .context(PythiaError::WithdrawRequestDoesNotExist)
+        })
+    }
+
+    pub fn get_by_chain(chain_id: &Nat) -> Vec<WithdrawRequest> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .withdraw_requests
+                .0
+                .get(chain_id)
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Requests made by the address or sent to it
+    pub fn get_by_address(address: &str) -> Vec<WithdrawRequest> {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .withdraw_requests
+                .0
+                .values()
+                .flatten()
+                .filter(|req| req.owner == address || req.receiver == address)
+                .cloned()
+                .collect()
+        })
+    }
+
     pub fn init_new_chain(chain_id: &Nat) -> Result<()> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
@@ -12,7 +12,10 @@ use ic_web3_rs::{
 
 use crate::{
     clone_with_state, log,
-    types::{asset_data::AssetData, balance::Balances, chains::Chains, errors::PythiaError},
+    types::{
+        asset_data::AssetData, balance::Balances, chains::Chains,
+        deposit_addresses::DepositAddresses, errors::PythiaError, ledger::LedgerEntryKind,
+    },
     update_state,
     utils::{address, canister, sybil},
 };
@@ -33,6 +36,30 @@ pub async fn pma() -> Result<String> {
     Ok(addr)
 }
 
+/// tECDSA derivation path of the user's deposit address
+pub fn deposit_derivation_path(address: &str) -> Result<Vec<Vec<u8>>> {
+    Ok(vec![address::to_h160(address)?.as_bytes().to_vec()])
+}
+
+pub async fn deposit_address(address: &str) -> Result<String> {
+    let address = address::normalize(address)?;
+    if let Some(deposit_address) = DepositAddresses::get(&address) {
+        return Ok(deposit_address);
+    }
+
+    let deposit_address = get_eth_addr(
+        None,
+        Some(deposit_derivation_path(&address)?),
+        clone_with_state!(key_name),
+    )
+    .await
+    .map(|addr| address::from_h160(&addr))
+    .map_err(|e| anyhow!("{e}"))?;
+
+    DepositAddresses::add(&address, &deposit_address);
+    Ok(deposit_address)
+}
+
 pub async fn pma_h160() -> Result<H160> {
     Ok(H160::from_str(&canister::pma().await?).expect("pma should be a valid address"))
 }
@@ -66,8 +93,21 @@ pub async fn fee(chain_id: &Nat) -> Result<Nat> {
     Chains::get_fee(chain_id)
 }
 
-pub fn collect_fee(chain_id: &Nat, receiver: &str, amount: &Nat) -> Result<()> {
-    Balances::add_amount(chain_id, receiver, amount).context(PythiaError::UnableToIncreaseBalance)
+pub fn collect_fee(
+    chain_id: &Nat,
+    receiver: &str,
+    amount: &Nat,
+    subscription_id: &Nat,
+) -> Result<()> {
+    Balances::add_amount(
+        chain_id,
+        receiver,
+        amount,
+        LedgerEntryKind::PlatformFee {
+            subscription_id: subscription_id.clone(),
+        },
+    )
+    .context(PythiaError::UnableToIncreaseBalance)
 }
 
 pub fn transform_ctx_tx() -> CallOptions {
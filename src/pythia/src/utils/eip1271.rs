@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use candid::Nat;
+use ic_web3_rs::{
+    ethabi::{self, Token},
+    transports::ic_http_client::CallOptions,
+    types::{Bytes, CallRequest, H160},
+    Error, Transport, Web3,
+};
+
+use super::{canister, web3};
+use crate::{metrics, retry_until_success, types::errors::PythiaError};
+
+const ERC1271_ABI: &[u8] = include_bytes!("../../assets/ERC1271ABI.json");
+const IS_VALID_SIGNATURE_FUNCTION: &str = "isValidSignature";
+/// `bytes4(keccak256("isValidSignature(bytes32,bytes)"))`, returned by a wallet that accepts the signature
+pub const MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// Check the signature of a contract wallet through the RPC of the chain
+///
+/// # Returns
+///
+/// `None` if there is no code at the address, otherwise whether the wallet accepted the signature
+pub async fn verify(
+    chain_id: &Nat,
+    address: H160,
+    hash: [u8; 32],
+    sig: &[u8],
+) -> Result<Option<bool>> {
+    let w3 = web3::instance(chain_id)?;
+
+    metrics!(inc RPC_OUTCALLS, "code");
+    let has_code = retry_until_success!(has_code(&w3, address, canister::transform_ctx()))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "code");
+
+    if !has_code {
+        return Ok(None);
+    }
+
+    metrics!(inc RPC_OUTCALLS, "call");
+    let is_valid = retry_until_success!(is_valid_signature(
+        &w3,
+        address,
+        hash,
+        sig,
+        canister::transform_ctx()
+    ))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "call");
+
+    Ok(Some(is_valid))
+}
+
+pub async fn has_code<T: Transport>(
+    w3: &Web3<T>,
+    address: H160,
+    options: CallOptions,
+) -> Result<bool> {
+    let code = w3.eth().code(address, None, options).await?;
+
+    Ok(!code.0.is_empty())
+}
+
+/// Call `isValidSignature(hash, sig)` on the wallet, a reverted call or
+/// an unexpected output means that the signature is rejected
+pub async fn is_valid_signature<T: Transport>(
+    w3: &Web3<T>,
+    address: H160,
+    hash: [u8; 32],
+    sig: &[u8],
+    options: CallOptions,
+) -> Result<bool> {
+    let function = ethabi::Contract::load(ERC1271_ABI)
+        .context(PythiaError::InvalidContractABI)?
+        .function(IS_VALID_SIGNATURE_FUNCTION)
+        .context(PythiaError::InvalidContractABI)?
+        .clone();
+    let data = function
+        .encode_input(&[Token::FixedBytes(hash.to_vec()), Token::Bytes(sig.to_vec())])
+        .context(PythiaError::UnableToFormCallData)?;
+
+    let call_request = CallRequest {
+        to: Some(address),
+        data: Some(Bytes::from(data)),
+        ..Default::default()
+    };
+
+    let output = match w3.eth().call(call_request, None, options).await {
+        Ok(output) => output,
+        Err(Error::Rpc(_)) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let magic_value = function
+        .decode_output(&output.0)
+        .ok()
+        .and_then(|outputs| outputs.first().cloned())
+        .and_then(Token::into_fixed_bytes);
+
+    Ok(magic_value == Some(MAGIC_VALUE.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use futures::{
+        executor::block_on,
+        future::{self, BoxFuture, FutureExt},
+    };
+    use ic_web3_rs::{
+        helpers, transports::ic_http_client::CallOptionsBuilder, RequestId, Transport,
+    };
+    use jsonrpc_core::{Call, ErrorCode, Value};
+
+    use super::*;
+
+    type Response = std::result::Result<Value, jsonrpc_core::Error>;
+
+    /// Records the requests and replies with the queued responses
+    #[derive(Debug, Default, Clone)]
+    struct MockTransport {
+        requests: Rc<RefCell<Vec<(String, Vec<Value>)>>>,
+        responses: Rc<RefCell<VecDeque<Response>>>,
+    }
+
+    impl MockTransport {
+        fn new(responses: Vec<Response>) -> Self {
+            Self {
+                requests: Rc::default(),
+                responses: Rc::new(RefCell::new(responses.into())),
+            }
+        }
+    }
+
+    impl Transport for MockTransport {
+        type Out = BoxFuture<'static, ic_web3_rs::error::Result<Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            let mut requests = self.requests.borrow_mut();
+            requests.push((method.to_string(), params.clone()));
+            let id = requests.len();
+
+            (id, helpers::build_request(id, method, params))
+        }
+
+        fn send(&self, _id: RequestId, _request: Call, _options: CallOptions) -> Self::Out {
+            let response = match self.responses.borrow_mut().pop_front() {
+                Some(Ok(value)) => Ok(value),
+                Some(Err(e)) => Err(Error::Rpc(e)),
+                None => Err(Error::Unreachable),
+            };
+
+            future::ready(response).boxed()
+        }
+    }
+
+    const WALLET: &str = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+    const HASH: [u8; 32] = [0x11; 32];
+    const SIG: [u8; 3] = [0xde, 0xad, 0x01];
+
+    fn options() -> CallOptions {
+        CallOptionsBuilder::default()
+            .transform(None)
+            .cycles(None)
+            .max_resp(None)
+            .build()
+            .expect("failed to build call options")
+    }
+
+    fn wallet() -> H160 {
+        WALLET.parse().unwrap()
+    }
+
+    /// ABI-encoded `bytes4` output
+    fn output(value: [u8; 4]) -> Value {
+        let mut word = [0u8; 32];
+        word[..4].copy_from_slice(&value);
+
+        Value::String(format!("0x{}", hex::encode(word)))
+    }
+
+    #[test]
+    fn has_code_test() {
+        let transport = MockTransport::new(vec![
+            Ok(Value::String("0x6080604052".into())),
+            Ok(Value::String("0x".into())),
+        ]);
+        let w3 = Web3::new(transport.clone());
+
+        assert!(block_on(has_code(&w3, wallet(), options())).unwrap());
+        assert!(!block_on(has_code(&w3, wallet(), options())).unwrap());
+
+        let requests = transport.requests.borrow();
+        assert_eq!(requests[0].0, "eth_getCode");
+        assert_eq!(requests[0].1[0], Value::String(WALLET.into()));
+    }
+
+    #[test]
+    fn is_valid_signature_encodes_call_test() {
+        let transport = MockTransport::new(vec![Ok(output(MAGIC_VALUE))]);
+        let w3 = Web3::new(transport.clone());
+
+        block_on(is_valid_signature(&w3, wallet(), HASH, &SIG, options())).unwrap();
+
+        let requests = transport.requests.borrow();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0, "eth_call");
+
+        let call = &requests[0].1[0];
+        assert_eq!(call["to"], Value::String(WALLET.into()));
+
+        let data = call["data"].as_str().unwrap();
+        let expected = [
+            // the selector of `isValidSignature` is the magic value itself
+            hex::encode(MAGIC_VALUE),
+            hex::encode(HASH),
+            // offset of the signature
+            format!("{:0>64}", "40"),
+            format!("{:0>64}", "3"),
+            format!("{:0<64}", hex::encode(SIG)),
+        ]
+        .concat();
+        assert_eq!(data, format!("0x{expected}"));
+    }
+
+    #[test]
+    fn is_valid_signature_accepts_magic_value_test() {
+        let w3 = Web3::new(MockTransport::new(vec![Ok(output(MAGIC_VALUE))]));
+
+        assert!(block_on(is_valid_signature(&w3, wallet(), HASH, &SIG, options())).unwrap());
+    }
+
+    #[test]
+    fn is_valid_signature_rejects_other_values_test() {
+        let w3 = Web3::new(MockTransport::new(vec![
+            Ok(output([0xff, 0xff, 0xff, 0xff])),
+            Ok(Value::String("0x".into())),
+            Err(jsonrpc_core::Error {
+                code: ErrorCode::ServerError(3),
+                message: "execution reverted".into(),
+                data: None,
+            }),
+        ]));
+
+        for _ in 0..3 {
+            assert!(!block_on(is_valid_signature(&w3, wallet(), HASH, &SIG, options())).unwrap());
+        }
+    }
+
+    #[test]
+    fn is_valid_signature_fails_on_transport_error_test() {
+        let w3 = Web3::new(MockTransport::new(vec![]));
+
+        assert!(block_on(is_valid_signature(&w3, wallet(), HASH, &SIG, options())).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{Context, Result};
+use candid::{CandidType, Nat};
+use ic_web3_rs::{
+    contract::{Contract, Options},
+    ethabi::{self, Token},
+    types::{TransactionReceipt, H160, H256, U256},
+    Transport, Web3,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{canister, nat, sybil, web3};
+use crate::{
+    metrics, retry_until_success,
+    types::{asset_data::AssetData, chains::Chains, errors::PythiaError},
+};
+
+const ERC20_ABI: &[u8] = include_bytes!("../../assets/ERC20ABI.json");
+const TRANSFER_EVENT: &str = "Transfer";
+const TRANSFER_FUNCTION: &str = "transfer";
+pub const TOKEN_TRANSFER_GAS_LIMIT: u64 = 65_000;
+const NATIVE_DECIMALS: u32 = 18;
+
+/// Rate of the chain's native coin in the token, used to convert gas costs
+#[derive(Clone, Debug, Default, CandidType, Serialize, Deserialize)]
+pub struct TokenRate {
+    pub rate: Nat,
+    pub rate_decimals: u32,
+    pub token_decimals: u32,
+}
+
+impl TokenRate {
+    /// Converts an amount in the native coin to the token units, rounding up
+    pub fn to_token(&self, value: &Nat) -> Nat {
+        let numerator = value.clone() * self.rate.clone() * pow10(self.token_decimals);
+        let denominator = pow10(NATIVE_DECIMALS + self.rate_decimals);
+
+        (numerator + denominator.clone() - 1) / denominator
+    }
+
+    /// Converts an amount in the token units to the native coin, rounding down
+    pub fn to_native(&self, amount: &Nat) -> Nat {
+        #[allow(clippy::cmp_owned)]
+        if self.rate == Nat::from(0) {
+            return Nat::from(0);
+        }
+
+        (amount.clone() * pow10(NATIVE_DECIMALS + self.rate_decimals))
+            / (self.rate.clone() * pow10(self.token_decimals))
+    }
+}
+
+fn pow10(exp: u32) -> Nat {
+    Nat(num_bigint::BigUint::from(10u32).pow(exp))
+}
+
+fn abi() -> Result<ethabi::Contract> {
+    ethabi::Contract::load(ERC20_ABI).context(PythiaError::InvalidContractABI)
+}
+
+/// Get rates for every token configured on the chain, token address => rate
+pub async fn rates(chain_id: &Nat) -> Result<HashMap<String, TokenRate>> {
+    let mut rates = HashMap::new();
+    for token in Chains::get_tokens(chain_id)? {
+        let asset_data = sybil::get_asset_data(&token.feed_id)
+            .await
+            .context(PythiaError::UnableToGetTokenRate)?;
+
+        let (rate, rate_decimals) = match asset_data.data {
+            AssetData::DefaultPriceFeed { rate, decimals, .. } => (rate, decimals),
+            AssetData::CustomPriceFeed { rate, decimals, .. } => {
+                (rate, decimals.unwrap_or_default())
+            }
+            _ => return Err(PythiaError::UnsupportedAssetDataType.into()),
+        };
+
+        rates.insert(
+            token.address,
+            TokenRate {
+                rate: rate.into(),
+                rate_decimals: rate_decimals as u32,
+                token_decimals: token.decimals as u32,
+            },
+        );
+    }
+
+    Ok(rates)
+}
+
+/// Sum of the `Transfer` events of the `token` to the `receiver` in the tx receipt
+pub fn transferred_to(receipt: &TransactionReceipt, token: &H160, receiver: &H160) -> Result<Nat> {
+    let signature = abi()?
+        .event(TRANSFER_EVENT)
+        .context(PythiaError::InvalidContractABI)?
+        .signature();
+
+    let amount = receipt
+        .logs
+        .iter()
+        .filter(|log| {
+            log.address == *token
+                && log.topics.len() == 3
+                && log.topics[0] == signature
+                && H160::from(log.topics[2]) == *receiver
+        })
+        .fold(U256::zero(), |sum, log| {
+            sum + U256::from_big_endian(&log.data.0)
+        });
+
+    Ok(nat::from_u256(&amount))
+}
+
+/// Transfer `amount` of the `token` from the PMA to the `receiver` without waiting for the confirmation,
+/// returns the transaction hash and the nonce of the PMA it uses
+pub async fn transfer<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    token: H160,
+    receiver: H160,
+    amount: U256,
+) -> Result<(H256, U256)> {
+    let contract =
+        Contract::from_json(w3.eth(), token, ERC20_ABI).context(PythiaError::InvalidContractABI)?;
+    let from = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
+
+    metrics!(inc RPC_OUTCALLS, "gas_price");
+    let gas_price = retry_until_success!(w3.eth().gas_price(canister::transform_ctx()))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "gas_price");
+
+    metrics!(inc RPC_OUTCALLS, "transaction_count");
+    let nonce = retry_until_success!(w3.eth().transaction_count(
+        H160::from_str(&from)?,
+        None,
+        canister::transform_ctx()
+    ))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_count");
+
+    let options = Options {
+        gas_price: Some(gas_price),
+        gas: Some(U256::from(TOKEN_TRANSFER_GAS_LIMIT)),
+        nonce: Some(nonce),
+        ..Default::default()
+    };
+
+    let signed_call = contract
+        .sign(
+            TRANSFER_FUNCTION,
+            (receiver, amount),
+            options,
+            from,
+            web3::key_info(),
+            nat::to_u64(chain_id),
+        )
+        .await
+        .context(PythiaError::UnableToSignContractCall)?;
+    metrics!(inc ECDSA_SIGNS);
+
+    metrics!(inc RPC_OUTCALLS, "send_raw_transaction");
+    let tx_hash = retry_until_success!(w3.eth().send_raw_transaction(
+        signed_call.raw_transaction.clone(),
+        canister::transform_ctx()
+    ))
+    .context(PythiaError::UnableToExecuteRawTx)?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "send_raw_transaction");
+
+    Ok((tx_hash, nonce))
+}
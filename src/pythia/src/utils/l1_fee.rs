@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use ic_web3_rs::{
+    ethabi::{self, Token},
+    types::{Bytes, CallRequest, H160, U256},
+    Transport, Web3,
+};
+
+use super::canister;
+use crate::{
+    metrics, retry_until_success,
+    types::{chains::ChainFeeModel, errors::PythiaError},
+};
+
+const GAS_PRICE_ORACLE_ABI: &[u8] = include_bytes!("../../assets/GasPriceOracleABI.json");
+const NODE_INTERFACE_ABI: &[u8] = include_bytes!("../../assets/NodeInterfaceABI.json");
+const GET_L1_FEE_FUNCTION: &str = "getL1Fee";
+const GAS_ESTIMATE_L1_COMPONENT_FUNCTION: &str = "gasEstimateL1Component";
+/// OP Stack predeploy
+const GAS_PRICE_ORACLE_ADDRESS: &str = "0x420000000000000000000000000000000000000F";
+/// Arbitrum precompile, available only through `eth_call`
+const NODE_INTERFACE_ADDRESS: &str = "0x00000000000000000000000000000000000000C8";
+/// Upper estimate of the bytes a signed legacy transaction adds to its call data: the RLP envelope
+/// of the nonce, gas price, gas, receiver and value, and the 65 bytes of the signature
+pub const SIGNED_TX_OVERHEAD: usize = 110;
+
+/// L1 data fee of the transaction in wei, zero for the plain fee model
+///
+/// * `raw_tx` - signed transaction, used by the OP Stack oracle
+/// * `to`, `data` - receiver and call data of the transaction, used by the Arbitrum node interface
+pub async fn l1_fee<T: Transport>(
+    w3: &Web3<T>,
+    fee_model: &ChainFeeModel,
+    raw_tx: &[u8],
+    to: H160,
+    data: &[u8],
+) -> Result<U256> {
+    match fee_model {
+        ChainFeeModel::Plain => Ok(U256::zero()),
+        ChainFeeModel::OpStack => {
+            let outputs = call(
+                w3,
+                GAS_PRICE_ORACLE_ABI,
+                GAS_PRICE_ORACLE_ADDRESS,
+                GET_L1_FEE_FUNCTION,
+                &[Token::Bytes(raw_tx.to_vec())],
+            )
+            .await?;
+
+            outputs
+                .first()
+                .cloned()
+                .and_then(Token::into_uint)
+                .context(PythiaError::UnableToDecodeOutputs)
+        }
+        ChainFeeModel::Arbitrum => {
+            let outputs = call(
+                w3,
+                NODE_INTERFACE_ABI,
+                NODE_INTERFACE_ADDRESS,
+                GAS_ESTIMATE_L1_COMPONENT_FUNCTION,
+                &[
+                    Token::Address(to),
+                    Token::Bool(false),
+                    Token::Bytes(data.to_vec()),
+                ],
+            )
+            .await?;
+
+            // the L1 component is charged as L2 gas at the L2 base fee
+            let gas_estimate_for_l1 = outputs.first().cloned().and_then(Token::into_uint);
+            let base_fee = outputs.get(1).cloned().and_then(Token::into_uint);
+            match (gas_estimate_for_l1, base_fee) {
+                (Some(gas), Some(base_fee)) => Ok(gas * base_fee),
+                _ => Err(PythiaError::UnableToDecodeOutputs.into()),
+            }
+        }
+    }
+}
+
+async fn call<T: Transport>(
+    w3: &Web3<T>,
+    abi: &[u8],
+    address: &str,
+    function: &str,
+    params: &[Token],
+) -> Result<Vec<Token>> {
+    let function = ethabi::Contract::load(abi)
+        .context(PythiaError::InvalidContractABI)?
+        .function(function)
+        .context(PythiaError::InvalidContractABI)?
+        .clone();
+    let data = function
+        .encode_input(params)
+        .context(PythiaError::UnableToFormCallData)?;
+
+    let call_request = CallRequest {
+        to: Some(address.parse().context(PythiaError::InvalidAddressFormat)?),
+        data: Some(Bytes::from(data)),
+        ..Default::default()
+    };
+
+    metrics!(inc RPC_OUTCALLS, "call");
+    let raw_result =
+        retry_until_success!(w3
+            .eth()
+            .call(call_request.clone(), None, canister::transform_ctx()))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "call");
+
+    function
+        .decode_output(&raw_result.0)
+        .context(PythiaError::UnableToDecodeOutputs)
+}
@@ -170,6 +170,7 @@ pub struct Metrics {
     pub SYBIL_OUTCALLS: Metric,
     pub SUCCESSFUL_SYBIL_OUTCALLS: Metric,
     pub CYCLES: Metric,
+    pub DEFERRED_SUBSCRIPTIONS: Metric,
 }
 
 impl Metrics {
@@ -180,7 +181,8 @@ impl Metrics {
         self.SUCCESSFUL_RPC_OUTCALLS.encode(w)?;
         self.SYBIL_OUTCALLS.encode(w)?;
         self.SUCCESSFUL_SYBIL_OUTCALLS.encode(w)?;
-        self.CYCLES.encode(w)
+        self.CYCLES.encode(w)?;
+        self.DEFERRED_SUBSCRIPTIONS.encode(w)
     }
 }
 
@@ -233,6 +235,13 @@ thread_local! {
                 "Number of canister's cycles",
                 "gauge",
                 &[],
+            ),
+
+            DEFERRED_SUBSCRIPTIONS: Metric::new(
+                "deferred_subscriptions",
+                "Number of subscription executions deferred because the gas price exceeded the ceiling",
+                "counter",
+                &["chain"],
             )
     });
 }
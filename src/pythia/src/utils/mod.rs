@@ -9,10 +9,14 @@ use crate::log;
 pub mod abi;
 pub mod address;
 pub mod canister;
+pub mod eip1271;
+pub mod erc20;
+pub mod l1_fee;
 pub mod macros;
 pub mod metrics;
 pub mod multicall;
 pub mod nat;
+pub mod preflight;
 pub mod processors;
 pub mod signature;
 pub mod siwe;
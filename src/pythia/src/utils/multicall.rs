@@ -2,17 +2,18 @@ use anyhow::{Context, Result};
 use candid::Nat;
 use ic_web3_rs::{
     contract::{tokens::Tokenizable, Contract, Error, Options},
-    ethabi::Token,
-    types::{BlockId, Bytes, CallRequest, H160, U256},
+    ethabi::{self, Token},
+    ic::KeyInfo,
+    types::{BlockId, Bytes, CallRequest, H160, H256, U256},
     Transport, Web3,
 };
 use std::str::FromStr;
 
-use super::{address, canister, nat, web3};
+use super::{address, canister, l1_fee, nat, web3};
 use crate::{
     log, metrics, retry_until_success,
     types::{
-        chains::{Chain, Chains},
+        chains::{Chain, ChainFeeModel, Chains},
         errors::PythiaError,
         logger::PUBLISHER,
     },
@@ -71,6 +72,10 @@ pub struct MulticallResult {
     pub success: bool,
     pub used_gas: U256,
     pub return_data: Vec<u8>,
+    /// Hash of the transaction the call was executed in
+    pub tx_hash: Option<H256>,
+    /// Share of the transaction's L1 data fee
+    pub l1_fee: U256,
 }
 
 impl Tokenizable for MulticallResult {
@@ -90,6 +95,8 @@ impl Tokenizable for MulticallResult {
                     success,
                     used_gas,
                     return_data,
+                    tx_hash: None,
+                    l1_fee: U256::zero(),
                 });
             }
         }
@@ -136,6 +143,21 @@ impl Tokenizable for Transfer {
     }
 }
 
+/// Selectors of the functions the multicall contract should expose, function name => selector
+pub fn required_selectors() -> Result<Vec<(&'static str, [u8; 4])>> {
+    let abi = ethabi::Contract::load(MULTICALL_ABI).context(PythiaError::InvalidContractABI)?;
+
+    [MULTICALL_CALL_FUNCTION, MULTICALL_TRANSFER_FUNCTION]
+        .into_iter()
+        .map(|name| {
+            let function = abi
+                .function(name)
+                .context(PythiaError::InvalidContractABI)?;
+            Ok((name, function.short_signature()))
+        })
+        .collect()
+}
+
 pub async fn multicall<T: Transport>(
     w3: &Web3<T>,
     chain_id: &Nat,
@@ -158,21 +180,28 @@ pub async fn multicall<T: Transport>(
         let (current_calls_batch, _calls) = get_current_calls_batch(&calls, &chain);
         calls = _calls;
 
-        let results = execute_multicall_batch(
+        let (results, tx_hash, l1_fee) = execute_multicall_batch(
             w3,
             &from,
             &gas_price,
             &contract,
             &current_calls_batch,
-            chain_id,
+            &chain,
         )
         .await?;
 
+        // the L1 data fee is split evenly between the calls of the batch, rounding up
+        let calls_count = U256::from(results.len().max(1));
+        let l1_fee = (l1_fee + calls_count - 1) / calls_count;
+
         result.append(
             &mut results
                 .iter()
-                .map(|token| {
-                    MulticallResult::from_token(token.clone()).expect("failed to decode from token")
+                .map(|token| MulticallResult {
+                    tx_hash: Some(tx_hash),
+                    l1_fee,
+                    ..MulticallResult::from_token(token.clone())
+                        .expect("failed to decode from token")
                 })
                 .collect::<Vec<MulticallResult>>(),
         );
@@ -181,59 +210,67 @@ pub async fn multicall<T: Transport>(
     Ok(result)
 }
 
+/// Estimated share of the L1 data fee of every call, the calls are split into
+/// transactions the same way `multicall` does. Zeros for the plain fee model
+pub async fn estimate_l1_fees<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    calls: &[Call],
+) -> Result<Vec<U256>> {
+    let chain = Chains::get(chain_id)?;
+    let fee_model = chain.fee_model.clone().unwrap_or_default();
+    if fee_model == ChainFeeModel::Plain {
+        return Ok(vec![U256::zero(); calls.len()]);
+    }
+
+    let contract = multicall_contract(w3, chain_id)?;
+    let mut calls = calls.to_vec();
+    let mut l1_fees = vec![];
+    while !calls.is_empty() {
+        let (batch, rest) = get_current_calls_batch(&calls, &chain);
+        calls = rest;
+
+        let params: Vec<Token> = batch.iter().map(|c| c.clone().into_token()).collect();
+        let data = contract
+            .abi()
+            .function(MULTICALL_CALL_FUNCTION)
+            .and_then(|f| f.encode_input(&[params.into_token()]))
+            .context(PythiaError::UnableToFormCallData)?;
+
+        // the transaction is not signed yet, the call data padded to the length of the signed transaction
+        // stands for it, non-zero bytes cost the most on L1
+        let mut raw_tx = data.clone();
+        raw_tx.extend(vec![u8::MAX; l1_fee::SIGNED_TX_OVERHEAD]);
+        let l1_fee = l1_fee::l1_fee(w3, &fee_model, &raw_tx, contract.address(), &data)
+            .await
+            .context(PythiaError::UnableToGetL1Fee)?;
+
+        let calls_count = U256::from(batch.len().max(1));
+        l1_fees.extend(vec![(l1_fee + calls_count - 1) / calls_count; batch.len()]);
+    }
+
+    Ok(l1_fees)
+}
+
 async fn execute_multicall_batch<T: Transport>(
     w3: &Web3<T>,
     from: &str,
     gas_price: &U256,
     contract: &Contract<T>,
     batch: &[Call],
-    chain_id: &Nat,
-) -> Result<Vec<Token>> {
-    metrics!(inc RPC_OUTCALLS, "transaction_count");
-
-    let options = Options {
-        gas_price: Some(*gas_price),
-        gas: Some(
-            batch
-                .iter()
-                .fold(U256::from(BASE_GAS + GAS_FOR_OPS), |result, call| {
-                    result + call.gas_limit
-                }),
-        ),
-        nonce: Some(retry_until_success!(w3.eth().transaction_count(
-            H160::from_str(from)?,
-            None,
-            canister::transform_ctx()
-        ))?),
-        max_fee_per_gas: Some(*gas_price * 2),
-        ..Default::default()
-    };
-    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_count");
-
-    let params: Vec<Token> = batch.iter().map(|c| c.clone().into_token()).collect();
-
-    let signed_call = contract
-        .sign(
-            MULTICALL_CALL_FUNCTION,
-            vec![params.clone()],
-            options.clone(),
-            from.to_string(),
-            web3::key_info(),
-            nat::to_u64(chain_id),
-        )
-        .await
-        .context(PythiaError::UnableToSignContractCall)?;
-    metrics!(inc ECDSA_SIGNS);
-
-    log!("[{PUBLISHER}] chain: {}, tx was signed", chain_id);
-
-    metrics!(inc RPC_OUTCALLS, "send_raw_transaction");
-    let tx_hash = retry_until_success!(w3.eth().send_raw_transaction(
-        signed_call.raw_transaction.clone(),
-        canister::transform_ctx()
-    ))
-    .context(PythiaError::UnableToExecuteRawTx)?;
-    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "send_raw_transaction");
+    chain: &Chain,
+) -> Result<(Vec<Token>, H256, U256)> {
+    let chain_id = &chain.chain_id;
+    let (tx_hash, l1_fee) = send_multicall_batch(
+        w3,
+        from,
+        gas_price,
+        contract,
+        batch,
+        chain_id,
+        &chain.fee_model.clone().unwrap_or_default(),
+    )
+    .await?;
 
     log!("[{PUBLISHER}] chain: {}, tx was sent", chain_id);
     let tx_receipt = web3::wait_for_success_confirmation(w3, &tx_hash, TX_TIMEOUT)
@@ -241,6 +278,7 @@ async fn execute_multicall_batch<T: Transport>(
         .context(PythiaError::WaitingForSuccessConfirmationFailed)?;
     log!("[{PUBLISHER}] chain: {}, tx was executed", chain_id);
 
+    let params: Vec<Token> = batch.iter().map(|c| c.clone().into_token()).collect();
     let data = contract
         .abi()
         .function(MULTICALL_CALL_FUNCTION)
@@ -280,12 +318,89 @@ async fn execute_multicall_batch<T: Transport>(
         .and_then(|f| f.decode_output(&raw_result.0))
         .context(PythiaError::UnableToDecodeOutputs)?;
 
-    call_result
+    let results = call_result
         .first()
         .context(PythiaError::InvalidMulticallResult)?
         .clone()
         .into_array()
-        .context(PythiaError::InvalidMulticallResult)
+        .context(PythiaError::InvalidMulticallResult)?;
+
+    Ok((results, tx_hash, l1_fee))
+}
+
+async fn send_multicall_batch<T: Transport>(
+    w3: &Web3<T>,
+    from: &str,
+    gas_price: &U256,
+    contract: &Contract<T>,
+    batch: &[Call],
+    chain_id: &Nat,
+    fee_model: &ChainFeeModel,
+) -> Result<(H256, U256)> {
+    metrics!(inc RPC_OUTCALLS, "transaction_count");
+
+    let options = Options {
+        gas_price: Some(*gas_price),
+        gas: Some(
+            batch
+                .iter()
+                .fold(U256::from(BASE_GAS + GAS_FOR_OPS), |result, call| {
+                    result + call.gas_limit
+                }),
+        ),
+        nonce: Some(retry_until_success!(w3.eth().transaction_count(
+            H160::from_str(from)?,
+            None,
+            canister::transform_ctx()
+        ))?),
+        max_fee_per_gas: Some(*gas_price * 2),
+        ..Default::default()
+    };
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_count");
+
+    let params: Vec<Token> = batch.iter().map(|c| c.clone().into_token()).collect();
+
+    let signed_call = contract
+        .sign(
+            MULTICALL_CALL_FUNCTION,
+            vec![params.clone()],
+            options.clone(),
+            from.to_string(),
+            web3::key_info(),
+            nat::to_u64(chain_id),
+        )
+        .await
+        .context(PythiaError::UnableToSignContractCall)?;
+    metrics!(inc ECDSA_SIGNS);
+
+    log!("[{PUBLISHER}] chain: {}, tx was signed", chain_id);
+
+    // the PMA pays the L1 data fee on rollups, it's charged from the users of the batch,
+    // so the batch is not sent if the fee is unknown
+    let data = contract
+        .abi()
+        .function(MULTICALL_CALL_FUNCTION)
+        .and_then(|f| f.encode_input(&[params.into_token()]))
+        .context(PythiaError::UnableToFormCallData)?;
+    let l1_fee = l1_fee::l1_fee(
+        w3,
+        fee_model,
+        &signed_call.raw_transaction.0,
+        contract.address(),
+        &data,
+    )
+    .await
+    .context(PythiaError::UnableToGetL1Fee)?;
+
+    metrics!(inc RPC_OUTCALLS, "send_raw_transaction");
+    let tx_hash = retry_until_success!(w3.eth().send_raw_transaction(
+        signed_call.raw_transaction.clone(),
+        canister::transform_ctx()
+    ))
+    .context(PythiaError::UnableToExecuteRawTx)?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "send_raw_transaction");
+
+    Ok((tx_hash, l1_fee))
 }
 
 fn get_current_calls_batch(calls: &[Call], chain: &Chain) -> (Vec<Call>, Vec<Call>) {
@@ -300,24 +415,87 @@ fn get_current_calls_batch(calls: &[Call], chain: &Chain) -> (Vec<Call>, Vec<Cal
     (calls.to_vec(), vec![])
 }
 
-pub async fn multitransfer<T: Transport>(
-    w3: &Web3<T>,
-    chain_id: &Nat,
-    transfers: Vec<Transfer>,
-) -> Result<()> {
-    let chain = Chains::get(chain_id)?;
+/// Gas of the multitransfer transaction, estimated before the transfer values are finalized
+#[derive(Debug, Clone, Default)]
+pub struct MultitransferGas {
+    pub gas_limit: U256,
+    pub gas_price: U256,
+}
 
-    let contract_addr = address::to_h160(&chain.multicall_contract.clone().unwrap())?;
-    let contract = Contract::from_json(w3.eth(), contract_addr, MULTICALL_ABI)
-        .context(PythiaError::InvalidContractABI)?;
+impl MultitransferGas {
+    pub fn cost(&self) -> U256 {
+        self.gas_limit * self.gas_price
+    }
 
-    let from = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
-    let key_info = web3::key_info();
+    /// Cost of the transaction split evenly between the transfers, rounded up
+    pub fn cost_per_transfer(&self, transfers_count: usize) -> U256 {
+        let count = U256::from(transfers_count.max(1));
+        (self.cost() + count - 1) / count
+    }
+}
+
+/// Estimate the gas of the multitransfer from the `from` address
+pub async fn estimate_multitransfer<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    transfers: &[Transfer],
+    from: &str,
+) -> Result<MultitransferGas> {
+    let contract = multicall_contract(w3, chain_id)?;
 
     metrics!(inc RPC_OUTCALLS, "gas_price");
     let gas_price = retry_until_success!(w3.eth().gas_price(canister::transform_ctx()))?;
     metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "gas_price");
 
+    let params: Vec<Token> = transfers.iter().map(|c| c.clone().into_token()).collect();
+    let options = Options {
+        gas_price: Some(gas_price),
+        value: Some(transfers.iter().fold(U256::from(0), |sum, t| sum + t.value)),
+        ..Default::default()
+    };
+
+    let gas_limit = contract
+        .estimate_gas(
+            MULTICALL_TRANSFER_FUNCTION,
+            params,
+            H160::from_str(from)?,
+            options,
+        )
+        .await
+        .context(PythiaError::UnableToEstimateGas)?;
+
+    Ok(MultitransferGas {
+        gas_limit,
+        gas_price,
+    })
+}
+
+/// Send the multitransfer from the PMA without waiting for the confirmation, returns the transaction hash
+/// and the nonce of the PMA it uses
+pub async fn send_multitransfer<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    transfers: Vec<Transfer>,
+    gas: &MultitransferGas,
+) -> Result<(H256, U256)> {
+    let from = canister::pma().await.context(PythiaError::UnableToGetPMA)?;
+
+    send_multitransfer_from(w3, chain_id, transfers, from, web3::key_info(), gas).await
+}
+
+/// Send the multitransfer from the `from` address signed with the `key_info` without waiting for the confirmation,
+/// returns the transaction hash and the nonce of the sender it uses.
+/// The transaction value is the sum of the transfers, the gas is paid by the sender on top of it
+pub async fn send_multitransfer_from<T: Transport>(
+    w3: &Web3<T>,
+    chain_id: &Nat,
+    transfers: Vec<Transfer>,
+    from: String,
+    key_info: KeyInfo,
+    gas: &MultitransferGas,
+) -> Result<(H256, U256)> {
+    let contract = multicall_contract(w3, chain_id)?;
+
     let params: Vec<Token> = transfers.iter().map(|c| c.clone().into_token()).collect();
 
     let value = transfers.iter().fold(U256::from(0), |sum, t| sum + t.value);
@@ -330,26 +508,14 @@ pub async fn multitransfer<T: Transport>(
     ))?;
     metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_count");
 
-    let mut options = Options {
-        gas_price: Some(gas_price),
+    let options = Options {
+        gas_price: Some(gas.gas_price),
+        gas: Some(gas.gas_limit),
         value: Some(value),
         nonce: Some(nonce),
         ..Default::default()
     };
 
-    let gas_limit = contract
-        .estimate_gas(
-            &MULTICALL_TRANSFER_FUNCTION,
-            params.clone(),
-            H160::from_str(&from)?,
-            options.clone(),
-        )
-        .await
-        .context(PythiaError::UnableToEstimateGas)?;
-
-    options.value = Some(value - (gas_limit / transfers.len()) * gas_price);
-    options.gas = Some(gas_limit);
-
     let signed_call = contract
         .sign(
             MULTICALL_TRANSFER_FUNCTION,
@@ -373,11 +539,13 @@ pub async fn multitransfer<T: Transport>(
     .context(PythiaError::UnableToExecuteRawTx)?;
     metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "send_raw_transaction");
 
-    web3::wait_for_success_confirmation(w3, &tx_hash, TX_TIMEOUT)
-        .await
-        .context(PythiaError::WaitingForSuccessConfirmationFailed)?;
+    Ok((tx_hash, nonce))
+}
 
-    log!("[Multitransfer] tx received, chain_id: {}", chain_id);
+fn multicall_contract<T: Transport>(w3: &Web3<T>, chain_id: &Nat) -> Result<Contract<T>> {
+    let chain = Chains::get(chain_id)?;
 
-    Ok(())
+    let contract_addr = address::to_h160(&chain.multicall_contract.clone().unwrap())?;
+    Contract::from_json(w3.eth(), contract_addr, MULTICALL_ABI)
+        .context(PythiaError::InvalidContractABI)
 }
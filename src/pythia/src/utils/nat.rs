@@ -22,3 +22,19 @@ pub fn from_u256(u256: &U256) -> Nat {
 
     Nat(num_bigint::BigUint::from_bytes_be(&buf))
 }
+
+pub fn saturating_sub(lhs: &Nat, rhs: &Nat) -> Nat {
+    if lhs > rhs {
+        lhs.clone() - rhs.clone()
+    } else {
+        Nat::from(0)
+    }
+}
+
+pub fn max(lhs: &Nat, rhs: &Nat) -> Nat {
+    if lhs > rhs {
+        lhs.clone()
+    } else {
+        rhs.clone()
+    }
+}
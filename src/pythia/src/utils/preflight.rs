@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use candid::Nat;
+use ic_web3_rs::{
+    transports::ICHttp,
+    types::{BlockId, BlockNumber},
+    Transport, Web3,
+};
+
+use super::{address, canister, multicall, nat};
+use crate::{
+    log, metrics, retry_until_success,
+    types::{errors::PythiaError, logger::CHAINS},
+};
+
+/// Check the chain configuration against the live RPC before it's saved
+pub async fn check_chain(
+    rpc: &str,
+    chain_id: &Nat,
+    block_gas_limit: &Nat,
+    multicall_contract: &str,
+) -> Result<()> {
+    let w3 = Web3::new(ICHttp::new(rpc, None).context(PythiaError::InvalidChainRPC)?);
+
+    check_chain_id(&w3, chain_id).await?;
+    check_multicall_contract(&w3, multicall_contract).await?;
+    check_block_gas_limit(&w3, block_gas_limit).await?;
+
+    log!("[{CHAINS}] preflight checks passed, id: {chain_id}");
+    Ok(())
+}
+
+async fn check_chain_id<T: Transport>(w3: &Web3<T>, chain_id: &Nat) -> Result<()> {
+    metrics!(inc RPC_OUTCALLS, "chain_id");
+    let actual = retry_until_success!(w3.eth().chain_id(canister::transform_ctx()))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "chain_id");
+
+    let actual = nat::from_u256(&actual);
+    if actual != *chain_id {
+        return Err(PythiaError::ChainIdMismatch {
+            expected: chain_id.to_string(),
+            actual: actual.to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The contract should be deployed and its dispatcher should contain the selectors of the used functions
+async fn check_multicall_contract<T: Transport>(
+    w3: &Web3<T>,
+    multicall_contract: &str,
+) -> Result<()> {
+    metrics!(inc RPC_OUTCALLS, "code");
+    let code = retry_until_success!(w3.eth().code(
+        address::to_h160(multicall_contract)?,
+        None,
+        canister::transform_ctx()
+    ))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "code");
+
+    if code.0.is_empty() {
+        return Err(PythiaError::MulticallContractHasNoCode(multicall_contract.to_string()).into());
+    }
+
+    for (name, selector) in multicall::required_selectors()? {
+        if !code
+            .0
+            .windows(selector.len())
+            .any(|window| window == selector)
+        {
+            return Err(PythiaError::MulticallContractMissingFunction(name.to_string()).into());
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_block_gas_limit<T: Transport>(w3: &Web3<T>, block_gas_limit: &Nat) -> Result<()> {
+    metrics!(inc RPC_OUTCALLS, "block");
+    let block = retry_until_success!(w3.eth().block(
+        BlockId::Number(BlockNumber::Latest),
+        canister::transform_ctx()
+    ))?
+    .context("latest block should be present")?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "block");
+
+    let actual = nat::from_u256(&block.gas_limit);
+    if *block_gas_limit > actual {
+        return Err(PythiaError::BlockGasLimitExceeded {
+            configured: block_gas_limit.to_string(),
+            actual: actual.to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
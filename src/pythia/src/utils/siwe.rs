@@ -1,34 +1,218 @@
 use std::str::FromStr;
 
-use anyhow::Result;
-use siwe::{Message, VerificationOpts};
+use candid::Nat;
+use ic_web3_rs::types::H160;
+use siwe::{Message, VerificationError, VerificationOpts};
 use time::OffsetDateTime;
-// use time::OffsetDateTime;
 
-use super::address;
-use ic_cdk::api::time;
-use std::time::Duration;
+use super::{address, eip1271, time::in_seconds};
+use crate::types::{
+    chains::Chains,
+    session::SessionPermission,
+    siwe::{SiweAction, SiweConfig, SiweError, SiweNonces},
+};
 
-pub async fn siwe_recover(msg: &str, sig: &str) -> Result<String> {
-    let msg = Message::from_str(&msg).expect("must be valid message");
+const SIGNATURE_LENGTH: usize = 65;
 
-    let sig = hex::decode(sig).expect("must be valid hex");
+/// Verifies the signature and the validity window of a SIWE message and
+/// returns the normalized signer address. Doesn't consume the nonce.
+/// Signatures of contract wallets are checked with EIP-1271 on the chain of the message.
+pub async fn siwe_recover(msg: &str, sig: &str) -> Result<String, SiweError> {
+    let msg = parse(msg)?;
 
-    let timestamp = OffsetDateTime::from_unix_timestamp(
-        (Duration::from_nanos(time()).as_secs() / 1_000_000_000) as i64,
-    )
-    .expect("must be valid timestamp");
+    verify(&msg, sig).await
+}
+
+/// Verifies a SIWE message authorizing `action` on `chain_id` and consumes
+/// its nonce, so the same message can't be used twice. The nonce must be
+/// issued to the caller.
+///
+/// # Arguments
+///
+/// * `msg` - SIWE message
+/// * `sig` - Signature of the message
+/// * `action` - Action the message must authorize
+/// * `chain_id` - Chain the message must be issued for
+///
+/// # Returns
+///
+/// Returns the normalized signer address
+pub async fn siwe_authorize(
+    msg: &str,
+    sig: &str,
+    action: SiweAction,
+    chain_id: &Nat,
+) -> Result<String, SiweError> {
+    let msg = parse(msg)?;
+
+    authorize(&msg, sig, action, Some(chain_id)).await
+}
+
+/// Verifies a SIWE message authorizing `action` that isn't bound to a chain
+/// and consumes its nonce.
+///
+/// # Returns
+///
+/// Returns the normalized signer address
+pub async fn siwe_authorize_unbound(
+    msg: &str,
+    sig: &str,
+    action: SiweAction,
+) -> Result<String, SiweError> {
+    let msg = parse(msg)?;
+
+    authorize(&msg, sig, action, None).await
+}
+
+/// Verifies a SIWE login message and consumes its nonce. The message isn't
+/// bound to a chain, the session permissions are listed in its resources.
+///
+/// # Returns
+///
+/// Returns the normalized signer address and the granted session permissions
+pub async fn siwe_login(
+    msg: &str,
+    sig: &str,
+) -> Result<(String, Vec<SessionPermission>), SiweError> {
+    let msg = parse(msg)?;
+
+    let address = authorize(&msg, sig, SiweAction::Login, None).await?;
+    let permissions = msg
+        .resources
+        .iter()
+        .filter_map(|resource| SessionPermission::from_resource(resource.as_str()))
+        .collect();
+
+    Ok((address, permissions))
+}
+
+/// The cheap checks of the message and its nonce go first, so a made-up message
+/// is rejected before the signature check can make EIP-1271 outcalls
+async fn authorize(
+    msg: &Message,
+    sig: &str,
+    action: SiweAction,
+    chain_id: Option<&Nat>,
+) -> Result<String, SiweError> {
+    let caller = ic_cdk::caller();
+
+    let domain = msg.domain.to_string();
+    if !SiweConfig::is_domain_allowed(&domain) {
+        return Err(SiweError::DomainNotAllowed(domain));
+    }
+
+    let uri = msg.uri.to_string();
+    if !SiweConfig::is_uri_allowed(&uri) {
+        return Err(SiweError::UriNotAllowed(uri));
+    }
+
+    if let Some(chain_id) = chain_id {
+        if Nat::from(msg.chain_id) != *chain_id {
+            return Err(SiweError::ChainIdMismatch {
+                expected: chain_id.to_string(),
+                actual: msg.chain_id.to_string(),
+            });
+        }
+    }
+
+    if !msg
+        .resources
+        .iter()
+        .any(|resource| resource.as_str() == action.resource())
+    {
+        return Err(SiweError::ActionNotAuthorized(
+            action.resource().to_string(),
+        ));
+    }
+
+    SiweNonces::check(&msg.nonce, caller)?;
+
+    let address = verify(msg, sig).await?;
+
+    SiweNonces::consume(&msg.nonce, caller)?;
+
+    Ok(address)
+}
+
+fn parse(msg: &str) -> Result<Message, SiweError> {
+    Message::from_str(msg).map_err(|e| SiweError::MalformedMessage(e.to_string()))
+}
+
+fn decode_signature(sig: &str) -> Result<Vec<u8>, SiweError> {
+    hex::decode(sig.strip_prefix("0x").unwrap_or(sig))
+        .map_err(|e| SiweError::InvalidHex(e.to_string()))
+}
+
+async fn verify(msg: &Message, sig: &str) -> Result<String, SiweError> {
+    let sig = decode_signature(sig)?;
+
+    let timestamp =
+        OffsetDateTime::from_unix_timestamp(in_seconds() as i64).expect("must be valid timestamp");
+
+    if let Some(expiration_time) = &msg.expiration_time {
+        if *expiration_time.as_ref() <= timestamp {
+            return Err(SiweError::Expired);
+        }
+    }
+    if let Some(not_before) = &msg.not_before {
+        if *not_before.as_ref() > timestamp {
+            return Err(SiweError::NotYetValid);
+        }
+    }
 
     let opts = VerificationOpts {
         timestamp: Some(timestamp),
         ..Default::default()
     };
 
-    msg.verify(&sig, &opts)
-        .await
-        .expect("must be valid signature");
+    let result = if sig.len() == SIGNATURE_LENGTH {
+        msg.verify(&sig, &opts).await
+    } else {
+        Err(VerificationError::SignatureLength)
+    };
+
+    match result {
+        Ok(_) => {}
+        // a contract wallet can't produce an ECDSA signature of its own address
+        Err(VerificationError::Signer) => {
+            verify_contract_signature(msg, &sig, SiweError::SignatureMismatch).await?
+        }
+        Err(VerificationError::SignatureLength) => {
+            verify_contract_signature(msg, &sig, SiweError::InvalidSignatureLength(sig.len()))
+                .await?
+        }
+        Err(VerificationError::Crypto(e)) => {
+            verify_contract_signature(msg, &sig, SiweError::InvalidSignature(e.to_string())).await?
+        }
+        Err(VerificationError::Time) => return Err(SiweError::Expired),
+        Err(e) => return Err(SiweError::InvalidSignature(e.to_string())),
+    }
+
+    address::normalize(&hex::encode(msg.address))
+        .map_err(|e| SiweError::InvalidSignature(e.to_string()))
+}
+
+/// Asks the message address to validate the signature with EIP-1271 through the
+/// RPC of the message chain. `error` is returned if the address isn't a contract
+/// or the chain isn't supported.
+async fn verify_contract_signature(
+    msg: &Message,
+    sig: &[u8],
+    error: SiweError,
+) -> Result<(), SiweError> {
+    let chain_id = Nat::from(msg.chain_id);
+    if !Chains::is_exists(&chain_id) {
+        return Err(error);
+    }
 
-    let signer = hex::encode(msg.address);
+    let hash = msg
+        .eip191_hash()
+        .map_err(|e| SiweError::MalformedMessage(e.to_string()))?;
 
-    address::normalize(&signer)
+    match eip1271::verify(&chain_id, H160::from(msg.address), hash, sig).await {
+        Ok(Some(true)) => Ok(()),
+        Ok(Some(false)) => Err(SiweError::ContractSignatureRejected),
+        Ok(None) => Err(error),
+        Err(e) => Err(SiweError::ContractCall(e.to_string())),
+    }
 }
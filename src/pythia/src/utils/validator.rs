@@ -2,7 +2,13 @@ use anyhow::Result;
 use candid::Nat;
 use ic_cdk::api::is_controller;
 
-use crate::PythiaError;
+use crate::{
+    types::{
+        roles::{Role, Roles},
+        whitelist,
+    },
+    PythiaError,
+};
 
 pub fn subscription_frequency(frequency: Nat, timer_frequency: Nat) -> Result<()> {
     #[allow(clippy::cmp_owned)]
@@ -21,6 +27,20 @@ pub fn subscription_frequency(frequency: Nat, timer_frequency: Nat) -> Result<()
     Ok(())
 }
 
+/// The frequency of the owner's subscription can't be lower than the minimum of their whitelist tier
+pub fn tier_frequency(owner: &str, frequency: &Nat) -> Result<()> {
+    if let Some(tier) = whitelist::get_tier(owner) {
+        if *frequency < tier.min_frequency {
+            return Err(PythiaError::FrequencyIsLowerThanTierMinimum(
+                tier.min_frequency.to_string(),
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
 pub fn caller() -> Result<()> {
     if is_controller(&ic_cdk::caller()) {
         return Ok(());
@@ -28,3 +48,13 @@ pub fn caller() -> Result<()> {
 
     Err(PythiaError::NotAController.into())
 }
+
+/// Checks that the caller is a controller or has the role.
+pub fn role(role: Role) -> Result<()> {
+    let caller = ic_cdk::caller();
+    if is_controller(&caller) || Roles::has(&caller, role) {
+        return Ok(());
+    }
+
+    Err(PythiaError::MissingRole(format!("{role:?}")).into())
+}
@@ -8,7 +8,7 @@ use ic_cdk::api::management_canister::http_request::{TransformContext, Transform
 use ic_web3_rs::{
     ic::KeyInfo,
     transports::{ic_http_client::CallOptionsBuilder, ICHttp},
-    types::{Transaction, TransactionId, TransactionParameters, TransactionReceipt, H256},
+    types::{Transaction, TransactionId, TransactionParameters, TransactionReceipt, H256, U256},
     Transport, Web3,
 };
 
@@ -27,7 +27,42 @@ pub fn instance(chain_id: &Nat) -> Result<Web3<ICHttp>> {
     Ok(Web3::new(ICHttp::new(&Chains::get(chain_id)?.rpc, None)?))
 }
 
-pub async fn get_tx(chain_id: &Nat, tx_hash: &str) -> Result<Transaction> {
+/// Get the receipt of the transaction, `None` if it's not executed yet
+pub async fn get_receipt(chain_id: &Nat, tx_hash: &H256) -> Result<Option<TransactionReceipt>> {
+    let w3 = instance(chain_id)?;
+
+    metrics!(inc RPC_OUTCALLS, "transaction_receipt");
+    let tx_receipt = retry_until_success!(w3
+        .eth()
+        .transaction_receipt(*tx_hash, canister::transform_ctx_tx_with_logs()))
+    .context(PythiaError::UnableToGetTxReceipt)?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_receipt");
+
+    Ok(tx_receipt.filter(|receipt| receipt.status.is_some()))
+}
+
+/// Number of the address's transactions in the latest block, i.e. its next unused nonce
+pub async fn transaction_count(chain_id: &Nat, address: &str) -> Result<U256> {
+    let w3 = instance(chain_id)?;
+
+    metrics!(inc RPC_OUTCALLS, "transaction_count");
+    let nonce = retry_until_success!(w3.eth().transaction_count(
+        address::to_h160(address)?,
+        None,
+        canister::transform_ctx()
+    ))?;
+    metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "transaction_count");
+
+    Ok(nonce)
+}
+
+pub fn is_successful(receipt: &TransactionReceipt) -> bool {
+    receipt
+        .status
+        .is_some_and(|status| status.as_u64() == TX_SUCCESS_STATUS)
+}
+
+pub async fn get_tx(chain_id: &Nat, tx_hash: &str) -> Result<(Transaction, TransactionReceipt)> {
     let tx_hash = H256::from_str(tx_hash)?;
     let w3 = instance(chain_id)?;
 
@@ -55,7 +90,7 @@ pub async fn get_tx(chain_id: &Nat, tx_hash: &str) -> Result<Transaction> {
         metrics!(inc SUCCESSFUL_RPC_OUTCALLS, "get_tx");
     }
 
-    result
+    Ok((result?, tx_receipt))
 }
 
 pub async fn gas_price(chain_id: &Nat) -> Result<Nat> {
@@ -72,8 +107,13 @@ pub async fn gas_price(chain_id: &Nat) -> Result<Nat> {
 
 #[inline(always)]
 pub fn key_info() -> KeyInfo {
+    key_info_with_path(vec![vec![]])
+}
+
+#[inline(always)]
+pub fn key_info_with_path(derivation_path: Vec<Vec<u8>>) -> KeyInfo {
     KeyInfo {
-        derivation_path: vec![vec![]],
+        derivation_path,
         key_name: clone_with_state!(key_name),
         ecdsa_sign_cycles: Some(ECDSA_SIGN_CYCLES),
     }